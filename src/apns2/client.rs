@@ -0,0 +1,202 @@
+use a2::{
+    client::Endpoint,
+    error::Error,
+    response::{ErrorBody, Response},
+    signer::Signer,
+};
+use futures::{Future, Stream, future};
+use hyper::{Body, Client, Request};
+use hyper_alpn::AlpnConnector;
+use openssl::pkcs12::Pkcs12;
+use serde_json::{self, Value};
+use std::{io::Read, time::Duration};
+use tokio::timer::Timeout;
+
+/// How long APNs gets to respond.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Seconds a token signature is used before signing a new one. APNs refuses
+/// tokens older than an hour.
+const SIGNATURE_TTL: i64 = 55 * 60;
+
+pub type FutureResponse = Box<Future<Item = Response, Error = Error> + Send + 'static>;
+
+/// A notification to APNs: the device token, the `apns-*` headers and the
+/// JSON payload.
+pub struct ApnsRequest {
+    pub device_token: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub payload: Value,
+}
+
+/// An HTTP/2 connection to APNs. The requests are written here instead of
+/// with the `a2` client, which supports neither `apns-push-type` and
+/// `apns-collapse-id` nor the newer `aps` keys. The certificate handling,
+/// token signing and responses are from `a2`.
+pub struct ApnsClient {
+    http_client: Client<AlpnConnector>,
+    signer: Option<Signer>,
+    endpoint: Endpoint,
+}
+
+impl ApnsClient {
+    /// Authenticates with a PKCS #12 client certificate.
+    pub fn certificate<R>(pkcs12: &mut R, password: &str, endpoint: Endpoint) -> Result<ApnsClient, Error>
+    where
+        R: Read,
+    {
+        let mut der = Vec::new();
+        pkcs12.read_to_end(&mut der)?;
+
+        let parsed = Pkcs12::from_der(&der)?.parse(password)?;
+
+        let connector = AlpnConnector::with_client_cert(
+            &parsed.cert.to_pem()?,
+            &parsed.pkey.private_key_to_pem_pkcs8()?,
+        )?;
+
+        Ok(Self::new(connector, None, endpoint))
+    }
+
+    /// Authenticates with a token signed with the PKCS #8 key.
+    pub fn token<R>(pkcs8: &mut R, key_id: &str, team_id: &str, endpoint: Endpoint) -> Result<ApnsClient, Error>
+    where
+        R: Read,
+    {
+        let signer = Signer::new(pkcs8, key_id, team_id, SIGNATURE_TTL)?;
+
+        Ok(Self::new(AlpnConnector::new(), Some(signer), endpoint))
+    }
+
+    fn new(connector: AlpnConnector, signer: Option<Signer>, endpoint: Endpoint) -> ApnsClient {
+        let http_client = Client::builder()
+            .http2_only(true)
+            .build(connector);
+
+        ApnsClient {
+            http_client,
+            signer,
+            endpoint,
+        }
+    }
+
+    /// Sends the notification, failing with `Error::ResponseError` if APNs
+    /// doesn't accept it.
+    pub fn send(&self, request: ApnsRequest) -> FutureResponse {
+        let http_request = match Self::build_request(&self.endpoint, self.signer.as_ref(), request) {
+            Ok(http_request) => http_request,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        let sending = self.http_client
+            .request(http_request)
+            .map_err(|e| {
+                warn!("Error connecting to APNs"; "error" => format!("{:?}", e));
+                Error::ConnectionError
+            })
+            .and_then(|response| {
+                let code = response.status().as_u16();
+
+                let apns_id = response.headers()
+                    .get("apns-id")
+                    .and_then(|id| id.to_str().ok())
+                    .map(String::from);
+
+                response
+                    .into_body()
+                    .concat2()
+                    .map_err(|_| Error::ConnectionError)
+                    .and_then(move |body| {
+                        if code == 200 {
+                            return Ok(Response { error: None, apns_id, code });
+                        }
+
+                        let error = serde_json::from_slice::<ErrorBody>(&body).ok();
+
+                        Err(Error::ResponseError(Response { error, apns_id, code }))
+                    })
+            });
+
+        let timed = Timeout::new(sending, REQUEST_TIMEOUT).map_err(|e| {
+            if e.is_elapsed() {
+                warn!("APNs didn't respond in time");
+            }
+
+            e.into_inner().unwrap_or(Error::ConnectionError)
+        });
+
+        Box::new(timed)
+    }
+
+    fn build_request(
+        endpoint: &Endpoint,
+        signer: Option<&Signer>,
+        request: ApnsRequest,
+    ) -> Result<Request<Body>, Error> {
+        let mut builder = Request::builder();
+
+        builder
+            .method("POST")
+            .uri(format!("{}/3/device/{}", Self::base_url(endpoint), request.device_token));
+
+        for (name, value) in request.headers.iter() {
+            builder.header(*name, value.as_str());
+        }
+
+        if let Some(signer) = signer {
+            let authorization = signer.with_signature(|signature| format!("Bearer {}", signature))?;
+            builder.header("authorization", authorization.as_str());
+        }
+
+        let payload = serde_json::to_vec(&request.payload).map_err(|_| Error::SerializeError)?;
+
+        builder.body(Body::from(payload)).map_err(|e| {
+            warn!("Invalid APNs request"; "error" => format!("{:?}", e));
+            Error::ConnectionError
+        })
+    }
+
+    fn base_url(endpoint: &Endpoint) -> &'static str {
+        match *endpoint {
+            Endpoint::Production => "https://api.push.apple.com",
+            Endpoint::Sandbox => "https://api.sandbox.push.apple.com",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_request_for_the_endpoint() {
+        let request = ApnsRequest {
+            device_token: String::from("abc123"),
+            headers: vec![("apns-push-type", String::from("alert"))],
+            payload: json!({ "aps": { "alert": "Hello" } }),
+        };
+
+        let http_request = ApnsClient::build_request(&Endpoint::Sandbox, None, request).unwrap();
+
+        assert_eq!("POST", http_request.method().as_str());
+
+        assert_eq!(
+            "https://api.sandbox.push.apple.com/3/device/abc123",
+            http_request.uri().to_string()
+        );
+
+        assert_eq!("alert", http_request.headers()["apns-push-type"]);
+        assert!(http_request.headers().get("authorization").is_none());
+
+        let body = http_request.into_body().concat2().wait().unwrap();
+        let payload: Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(json!({ "aps": { "alert": "Hello" } }), payload);
+    }
+
+    #[test]
+    fn sends_to_the_production_or_sandbox_url() {
+        assert_eq!("https://api.push.apple.com", ApnsClient::base_url(&Endpoint::Production));
+        assert_eq!("https://api.sandbox.push.apple.com", ApnsClient::base_url(&Endpoint::Sandbox));
+    }
+}
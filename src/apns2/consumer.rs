@@ -1,8 +1,8 @@
-use futures::{Future, future::ok};
+use futures::{Future, future::{Either, ok}};
 
 use std::{
     collections::HashMap,
-    sync::RwLock,
+    sync::{Arc, RwLock},
 };
 
 use common::{
@@ -10,6 +10,7 @@ use common::{
         application::{
             Application,
             ConnectionEndpoint::{
+                self,
                 Production,
                 Sandbox
             },
@@ -19,24 +20,49 @@ use common::{
         push_notification::PushNotification,
         http_request::HttpRequest,
     },
-    kafka::EventHandler,
-    metrics::*
+    kafka::{EventHandler, Routing},
+    metrics::*,
+    tenants::{Connection, TENANTS},
+    transport::Transport,
 };
 
-use a2::{client::Endpoint, error::Error};
+use a2::{
+    ErrorReason,
+    Response,
+    client::Endpoint,
+    error::Error,
+};
 
-use notifier::Notifier;
+use notifier::{Credentials, Notifier, PushType, Topics};
 use producer::ApnsProducer;
 
+/// The notifiers of an application: one for the push types with credentials
+/// of their own, the default for the rest.
+struct ApplicationNotifiers {
+    default: Arc<Notifier>,
+    push_types: Vec<(PushType, Arc<Notifier>)>,
+}
+
+impl ApplicationNotifiers {
+    fn get(&self, push_type: &PushType) -> Arc<Notifier> {
+        self.push_types
+            .iter()
+            .find(|&&(ref p, _)| p == push_type)
+            .map(|&(_, ref notifier)| notifier)
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
 pub struct ApnsHandler {
     producer: ApnsProducer,
-    notifiers: RwLock<HashMap<String, Notifier>>,
+    notifiers: RwLock<HashMap<String, ApplicationNotifiers>>,
 }
 
 impl ApnsHandler {
-    pub fn new() -> ApnsHandler {
+    pub fn new(transport: Arc<Transport>) -> ApnsHandler {
         let notifiers = RwLock::new(HashMap::new());
-        let producer = ApnsProducer::new();
+        let producer = ApnsProducer::new(transport);
 
         ApnsHandler {
             producer,
@@ -44,54 +70,170 @@ impl ApnsHandler {
         }
     }
 
-    fn add_certificate_notifier(
-        &self,
+    fn certificate_notifier(
         certificate: &IosCertificate,
-        endpoint: Endpoint,
-        application_id: &str,
-        apns_topic: &str
-    ) -> Result<(), Error> {
+        topics: Topics,
+        sandbox_fallback: bool,
+    ) -> Result<Arc<Notifier>, Error> {
         let mut pkcs12 = certificate.get_pkcs12();
 
         let notifier = Notifier::certificate(
-        &mut pkcs12,
+            &mut pkcs12,
             certificate.get_password(),
-            endpoint,
-            apns_topic,
+            Self::endpoint(certificate.get_endpoint()),
+            topics,
         )?;
 
-        let mut notifiers = self.notifiers.write().unwrap();
-        notifiers.insert(application_id.to_string(), notifier);
+        let notifier = if sandbox_fallback {
+            notifier.with_sandbox_fallback(Credentials::Certificate {
+                pkcs12: certificate.get_pkcs12().to_vec(),
+                password: certificate.get_password().to_string(),
+            })
+        } else {
+            notifier
+        };
 
-        Ok(())
+        Ok(Arc::new(notifier))
     }
 
-    fn add_token_notifier(
-        &self,
+    fn token_notifier(
         token: &IosToken,
-        endpoint: Endpoint,
-        application_id: &str,
-        apns_topic: &str,
-    ) -> Result<(), Error> {
+        topics: Topics,
+        sandbox_fallback: bool,
+    ) -> Result<Arc<Notifier>, Error> {
         let mut pkcs8 = token.get_pkcs8();
 
         let notifier = Notifier::token(
             &mut pkcs8,
             token.get_key_id(),
             token.get_team_id(),
-            endpoint,
-            apns_topic,
+            Self::endpoint(token.get_endpoint()),
+            topics,
         )?;
 
-        let mut notifiers = self.notifiers.write().unwrap();
-        notifiers.insert(application_id.to_string(), notifier);
+        let notifier = if sandbox_fallback {
+            notifier.with_sandbox_fallback(Credentials::Token {
+                pkcs8: token.get_pkcs8().to_vec(),
+                key_id: token.get_key_id().to_string(),
+                team_id: token.get_team_id().to_string(),
+            })
+        } else {
+            notifier
+        };
+
+        Ok(Arc::new(notifier))
+    }
+
+    /// Notifiers for the push types with credentials of their own, sending
+    /// to the topic of the credentials as-is, or to the topic of the push type
+    /// for the `default_topic` if not set. Credentials that don't work are
+    /// skipped, the push type using the default notifier.
+    fn push_type_notifiers(
+        application: &Application,
+        default_topic: &str,
+    ) -> Vec<(PushType, Arc<Notifier>)> {
+        let sandbox_fallback = application.get_ios_config().get_sandbox_fallback();
+        let mut notifiers = Vec::new();
+
+        for credentials in application.get_ios_config().get_push_type_credentials() {
+            let push_type = match Notifier::parse_push_type(credentials.get_push_type()) {
+                Some(push_type) => push_type,
+                None => {
+                    warn!(
+                        "Unknown push type in credentials, ignoring";
+                        application,
+                        "push_type" => credentials.get_push_type()
+                    );
 
-        Ok(())
+                    continue;
+                }
+            };
+
+            let topics = |apns_topic: &str| {
+                if apns_topic.is_empty() {
+                    Topics::fixed(Topics::bundle(default_topic).get(&push_type))
+                } else {
+                    Topics::fixed(apns_topic)
+                }
+            };
+
+            let result = if credentials.has_token() {
+                let token = credentials.get_token();
+
+                info!(
+                    "Updating push type credentials";
+                    application,
+                    "push_type" => credentials.get_push_type(),
+                    "connection_type" => "token",
+                    "team_id" => token.get_team_id(),
+                    "key_id" => token.get_key_id(),
+                    "apns_topic" => token.get_apns_topic()
+                );
+
+                Self::token_notifier(token, topics(token.get_apns_topic()), sandbox_fallback)
+            } else if credentials.has_certificate() {
+                let certificate = credentials.get_certificate();
+
+                info!(
+                    "Updating push type credentials";
+                    application,
+                    "push_type" => credentials.get_push_type(),
+                    "connection_type" => "certificate",
+                    "apns_topic" => certificate.get_apns_topic()
+                );
+
+                Self::certificate_notifier(
+                    certificate,
+                    topics(certificate.get_apns_topic()),
+                    sandbox_fallback,
+                )
+            } else {
+                warn!(
+                    "No connection details for push type, ignoring";
+                    application,
+                    "push_type" => credentials.get_push_type()
+                );
+
+                continue;
+            };
+
+            match result {
+                Ok(notifier) => notifiers.push((push_type, notifier)),
+                Err(e) => error!(
+                    "Error connecting to APNs with the push type credentials, using the default";
+                    application,
+                    "push_type" => credentials.get_push_type(),
+                    "error" => format!("{:?}", e)
+                ),
+            }
+        }
+
+        notifiers
+    }
+
+    /// True if APNs doesn't know the device token, as with development
+    /// tokens sent to production.
+    fn bad_device_token(response: &Response) -> bool {
+        match response.error {
+            Some(ref error) => match error.reason {
+                ErrorReason::BadDeviceToken => true,
+                _ => false,
+            },
+            None => false,
+        }
+    }
+
+    fn endpoint(endpoint: ConnectionEndpoint) -> Endpoint {
+        match endpoint {
+            Production => Endpoint::Production,
+            Sandbox => Endpoint::Sandbox,
+        }
     }
 
     fn delete_notifier(&self, id: &str) {
         if self.notifiers.write().unwrap().remove(id).is_some() {
             self.set_app_counter();
+            TENANTS.removed(id);
             warn!("Application removed"; "universe" => id);
         }
     }
@@ -108,42 +250,73 @@ impl EventHandler for ApnsHandler {
 
     fn handle_notification(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
         let producer = self.producer.clone();
+
+        if let Err(reason) = Notifier::validate(&event) {
+            return producer.handle_invalid(routing, event, &reason);
+        }
+
         let timer = RESPONSE_TIMES_HISTOGRAM.start_timer();
 
         CALLBACKS_INFLIGHT.inc();
 
-        if let Some(notifier) = self.notifiers.read().unwrap().get(event.get_universe()) {
+        if let Some(notifiers) = self.notifiers.read().unwrap().get(event.get_universe()) {
+            let push_type = Notifier::push_type(&event);
+            let notifier = notifiers.get(&push_type);
+
             let notification_send = notifier
-                .notify(&event)
+                .notify(&event, push_type)
                 .then(move |result| {
+                    let bad_device_token = match result {
+                        Err(Error::ResponseError(ref response)) => Self::bad_device_token(response),
+                        _ => false,
+                    };
+
+                    let fallback = if bad_device_token {
+                        notifier.notify_sandbox(&event, Notifier::push_type(&event))
+                    } else {
+                        None
+                    };
+
+                    match fallback {
+                        Some(sandbox_send) => {
+                            info!("Bad device token in production, trying the sandbox"; &event);
+
+                            Either::A(sandbox_send.then(move |result| {
+                                let status = if result.is_ok() { "success" } else { "failure" };
+                                SANDBOX_FALLBACK_COUNTER.with_label_values(&[status]).inc();
+
+                                Ok::<_, ()>((result, "sandbox", event))
+                            }))
+                        }
+                        None => Either::B(ok((result, notifier.environment(), event))),
+                    }
+                })
+                .and_then(move |(result, environment, event)| {
                     timer.observe_duration();
                     CALLBACKS_INFLIGHT.dec();
 
                     match result {
-                        Ok(_) => producer.handle_ok(key, event),
-                        Err(Error::ResponseError(e)) => producer.handle_err(key, event, e),
-                        Err(e) => producer.handle_fatal(key, event, e),
+                        Ok(_) => producer.handle_ok(routing, event, environment),
+                        Err(Error::ResponseError(e)) => {
+                            producer.handle_err(routing, event, e, environment)
+                        }
+                        Err(e) => producer.handle_fatal(routing, event, e),
                     }
-                })
-                .then(|_| ok(()));
+                });
 
             Box::new(notification_send)
         } else {
-            let connection_error = producer
-                .handle_fatal(key, event, Error::ConnectionError)
-                .then(|_| ok(()));
-
-            Box::new(connection_error)
+            producer.handle_fatal(routing, event, Error::ConnectionError)
         }
     }
 
     fn handle_http(
         &self,
-        _: Option<Vec<u8>>,
+        _: Routing,
         _: HttpRequest,
     ) -> Box<Future<Item=(), Error=()> + 'static + Send> {
         warn!("We don't handle http request events here");
@@ -178,13 +351,10 @@ impl EventHandler for ApnsHandler {
                     return;
                 }
 
-                let result = if ios_config.has_token() {
+                let (result, connection) = if ios_config.has_token() {
                     let token_config = ios_config.get_token();
 
-                    let endpoint = match token_config.get_endpoint() {
-                        Production => Endpoint::Production,
-                        Sandbox => Endpoint::Sandbox,
-                    };
+                    let endpoint = Self::endpoint(token_config.get_endpoint());
 
                     info!(
                         "Updating application configuration";
@@ -193,49 +363,172 @@ impl EventHandler for ApnsHandler {
                         "team_id" => token_config.get_team_id(),
                         "key_id" => token_config.get_key_id(),
                         "apns_topic" => token_config.get_apns_topic(),
-                        "endpoint" => format!("{:?}", endpoint)
+                        "endpoint" => format!("{:?}", endpoint),
+                        "sandbox_fallback" => ios_config.get_sandbox_fallback()
                     );
 
-                    self.add_token_notifier(
+                    let connection = Connection {
+                        connection_type: String::from("token"),
+                        endpoint: Some(format!("{:?}", endpoint).to_lowercase()),
+                        topic: Some(token_config.get_apns_topic().to_string()),
+                        credentials: Some(format!(
+                            "team_id: {}, key_id: {}",
+                            token_config.get_team_id(),
+                            token_config.get_key_id(),
+                        )),
+                    };
+
+                    let result = Self::token_notifier(
                         token_config,
-                        endpoint,
-                        application_id,
-                        token_config.get_apns_topic(),
-                    )
+                        Topics::bundle(token_config.get_apns_topic()),
+                        ios_config.get_sandbox_fallback(),
+                    );
+
+                    (result, connection)
                 } else {
                     let cert_config = ios_config.get_certificate();
 
-                    let endpoint = match cert_config.get_endpoint() {
-                        Production => Endpoint::Production,
-                        Sandbox => Endpoint::Sandbox,
-                    };
+                    let endpoint = Self::endpoint(cert_config.get_endpoint());
 
                     info!(
                         "Updating application configuration";
                         &application,
                         "connection_type" => "certificate",
                         "apns_topic" => cert_config.get_apns_topic(),
-                        "endpoint" => format!("{:?}", endpoint)
+                        "endpoint" => format!("{:?}", endpoint),
+                        "sandbox_fallback" => ios_config.get_sandbox_fallback()
                     );
 
-                    self.add_certificate_notifier(
+                    let connection = Connection {
+                        connection_type: String::from("certificate"),
+                        endpoint: Some(format!("{:?}", endpoint).to_lowercase()),
+                        topic: Some(cert_config.get_apns_topic().to_string()),
+                        credentials: None,
+                    };
+
+                    let result = Self::certificate_notifier(
                         cert_config,
-                        endpoint,
-                        application_id,
-                        cert_config.get_apns_topic(),
-                    )
+                        Topics::bundle(cert_config.get_apns_topic()),
+                        ios_config.get_sandbox_fallback(),
+                    );
+
+                    (result, connection)
                 };
 
+                let default_topic = connection.topic.clone().unwrap_or_default();
+
+                let result = result.map(|default| {
+                    let push_types = Self::push_type_notifiers(&application, &default_topic);
+                    ApplicationNotifiers { default, push_types }
+                });
+
+                let result = result.map(|notifiers| {
+                    self.notifiers
+                        .write()
+                        .unwrap()
+                        .insert(application_id.to_string(), notifiers);
+                });
+
                 self.set_app_counter();
 
-                if let Err(error) = result {
-                    error!(
-                        "Error connecting to APNs";
-                        &application,
-                        "error" => format!("{:?}", error)
-                    )
-                };
+                match result {
+                    Ok(()) => TENANTS.loaded(application_id, connection),
+                    Err(error) => {
+                        TENANTS.failed(application_id, connection, &format!("{:?}", error));
+
+                        error!(
+                            "Error connecting to APNs";
+                            &application,
+                            "error" => format!("{:?}", error)
+                        )
+                    }
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        events::push_result::{PushResult, PushResult_ResponseAction as ResponseAction},
+        kafka::RequestConsumer,
+        transport::{ChannelHandle, ChannelTransport, Incoming},
+    };
+    use futures::{Stream, sync::oneshot};
+    use protobuf::{Message, parse_from_bytes};
+    use CONFIG;
+
+    fn notification(universe: &str, apple: bool) -> PushNotification {
+        let mut event = PushNotification::new();
+        event.set_universe(universe.to_string());
+        event.mut_header().set_field_type("notification.PushNotification".to_string());
+
+        if apple {
+            event.mut_apple();
+        }
+
+        event
+    }
+
+    /// Handles the notifications through a channel, returning the responses.
+    fn handle(events: Vec<PushNotification>) -> Vec<PushResult> {
+        let (transport, ChannelHandle { requests, mut responses }) = ChannelTransport::new();
+        let transport: Arc<Transport> = Arc::new(transport);
+        let handler = ApnsHandler::new(transport.clone());
+
+        for event in events {
+            let incoming = Incoming {
+                payload: event.write_to_bytes().unwrap(),
+                ..Default::default()
+            };
+
+            requests.unbounded_send(incoming).unwrap();
+        }
+
+        drop(requests);
+
+        let (_control_tx, control_rx) = oneshot::channel();
+
+        RequestConsumer::new(handler, transport, &CONFIG.kafka)
+            .handle_requests(control_rx)
+            .unwrap();
+
+        responses.close();
+
+        responses
+            .wait()
+            .map(|record| parse_from_bytes(&record.unwrap().payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn retries_notifications_of_unconfigured_tenants() {
+        let results = handle(vec![notification("unconfigured", true)]);
+
+        assert_eq!(1, results.len());
+        assert_eq!(ResponseAction::Retry, results[0].get_response_action());
+    }
+
+    #[test]
+    fn rejects_invalid_live_activities() {
+        let mut event = notification("unconfigured", true);
+
+        {
+            let live_activity = event.mut_apple().mut_live_activity();
+            live_activity.set_event("start".to_string());
+            live_activity.set_content_state("{}".to_string());
+        }
+
+        let results = handle(vec![event]);
+
+        assert_eq!(1, results.len());
+        assert_eq!(ResponseAction::None, results[0].get_response_action());
+    }
+
+    #[test]
+    fn skips_notifications_without_apple_data() {
+        assert!(handle(vec![notification("unconfigured", false)]).is_empty());
+    }
+}
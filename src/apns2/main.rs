@@ -1,4 +1,5 @@
 #[macro_use] extern crate lazy_static;
+#[macro_use] extern crate serde_json;
 #[macro_use] extern crate slog;
 #[macro_use] extern crate slog_scope;
 
@@ -6,29 +7,38 @@ extern crate a2;
 extern crate common;
 extern crate futures;
 extern crate heck;
-extern crate serde_json;
-extern crate tokio_timer;
+extern crate hyper;
+extern crate hyper_alpn;
+extern crate openssl;
+#[cfg(test)] extern crate protobuf;
+extern crate tokio;
 
+mod client;
 mod consumer;
 mod notifier;
 mod producer;
 
 use consumer::ApnsHandler;
-use std::env;
 
-use common::{config::Config, system::System};
+use common::{config::{self, Config, Options}, system::System, transport};
+
+const DEFAULT_CONFIG: &str = "./config/apns2.toml";
 
 lazy_static! {
-    pub static ref CONFIG: Config = match env::var("CONFIG") {
-        Ok(config_file_location) => Config::parse(&config_file_location),
-        _ => Config::parse("./config/fcm.toml"),
-    };
+    pub static ref CONFIG: Config = Config::parse(&config::location(DEFAULT_CONFIG));
 }
 
 fn main() {
+    if Options::parse("Sends push notifications to APNs").check_config {
+        Config::check(&config::location(DEFAULT_CONFIG));
+    }
+
+    let transport = transport::build(&CONFIG);
+
     System::start(
         "apns2",
-        ApnsHandler::new(),
+        ApnsHandler::new(transport.clone()),
+        transport,
         &CONFIG,
     );
 }
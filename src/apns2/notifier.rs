@@ -1,23 +1,138 @@
 use common::events::push_notification::PushNotification;
 use common::metrics::*;
 use serde_json::error::Error as JsonError;
-use serde_json::{self, Value};
+use serde_json::{self, Map, Value};
 use std::io::Read;
-use std::time::Duration;
-use tokio_timer::Timeout;
+use std::sync::Mutex;
 
-use a2::{client::{Client, Endpoint, FutureResponse}, error::Error,
-         request::{notification::*, payload::Payload}};
+use a2::{client::Endpoint, error::Error};
+use client::{ApnsClient, ApnsRequest, FutureResponse};
+
+/// APNs refuses longer `apns-collapse-id` headers.
+const MAX_COLLAPSE_ID_LENGTH: usize = 64;
+
+/// The `apns-push-type` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PushType {
+    Alert,
+    Background,
+    Voip,
+    Complication,
+    FileProvider,
+    Mdm,
+    Location,
+    LiveActivity,
+}
+
+impl PushType {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            PushType::Alert => "alert",
+            PushType::Background => "background",
+            PushType::Voip => "voip",
+            PushType::Complication => "complication",
+            PushType::FileProvider => "fileprovider",
+            PushType::Mdm => "mdm",
+            PushType::Location => "location",
+            PushType::LiveActivity => "liveactivity",
+        }
+    }
+}
 
 enum NotifierType {
     Token,
     Certificate,
 }
 
+/// The topics of an application. VoIP, complication, file provider,
+/// location and Live Activity pushes go to the bundle ID with a suffix,
+/// unless the notifier is for one push type only.
+pub struct Topics {
+    default: String,
+    voip: String,
+    complication: String,
+    file_provider: String,
+    location: String,
+    live_activity: String,
+}
+
+impl Topics {
+    /// Topics derived from the bundle ID.
+    pub fn bundle(bundle_id: &str) -> Topics {
+        Topics {
+            default: String::from(bundle_id),
+            voip: format!("{}.voip", bundle_id),
+            complication: format!("{}.complication", bundle_id),
+            file_provider: format!("{}.pushkit.fileprovider", bundle_id),
+            location: format!("{}.location-query", bundle_id),
+            live_activity: format!("{}.push-type.liveactivity", bundle_id),
+        }
+    }
+
+    /// The same topic for every push type.
+    pub fn fixed(topic: &str) -> Topics {
+        Topics {
+            default: String::from(topic),
+            voip: String::from(topic),
+            complication: String::from(topic),
+            file_provider: String::from(topic),
+            location: String::from(topic),
+            live_activity: String::from(topic),
+        }
+    }
+
+    /// The topic for the push type.
+    pub fn get(&self, push_type: &PushType) -> &str {
+        match *push_type {
+            PushType::Voip => &self.voip,
+            PushType::Complication => &self.complication,
+            PushType::FileProvider => &self.file_provider,
+            PushType::Location => &self.location,
+            PushType::LiveActivity => &self.live_activity,
+            _ => &self.default,
+        }
+    }
+}
+
+/// Credentials kept for building a client later.
+pub enum Credentials {
+    Certificate {
+        pkcs12: Vec<u8>,
+        password: String,
+    },
+    Token {
+        pkcs8: Vec<u8>,
+        key_id: String,
+        team_id: String,
+    },
+}
+
+impl Credentials {
+    fn client(&self, endpoint: Endpoint) -> Result<ApnsClient, Error> {
+        match *self {
+            Credentials::Certificate { ref pkcs12, ref password } => {
+                ApnsClient::certificate(&mut pkcs12.as_slice(), password, endpoint)
+            }
+            Credentials::Token { ref pkcs8, ref key_id, ref team_id } => {
+                ApnsClient::token(&mut pkcs8.as_slice(), key_id, team_id, endpoint)
+            }
+        }
+    }
+}
+
+/// A client to the sandbox for the development tokens sent to production,
+/// built on first use.
+struct SandboxFallback {
+    credentials: Credentials,
+    client: Mutex<Option<ApnsClient>>,
+}
+
 pub struct Notifier {
-    client: Client,
-    topic: String,
+    client: ApnsClient,
+    topics: Topics,
     notifier_type: NotifierType,
+    environment: &'static str,
+    sandbox: Option<SandboxFallback>,
 }
 
 impl Drop for Notifier {
@@ -38,19 +153,22 @@ impl Notifier {
         pkcs12: &mut R,
         password: &str,
         endpoint: Endpoint,
-        topic: &str,
+        topics: Topics,
     ) -> Result<Notifier, Error>
     where
         R: Read,
     {
-        let client = Client::certificate(pkcs12, password, endpoint)?;
+        let environment = Self::endpoint_name(&endpoint);
+        let client = ApnsClient::certificate(pkcs12, password, endpoint)?;
         let notifier_type = NotifierType::Certificate;
         CERTIFICATE_CONSUMERS.inc();
 
         Ok(Notifier {
             client,
-            topic: String::from(topic),
+            topics,
             notifier_type,
+            environment,
+            sandbox: None,
         })
     }
 
@@ -59,106 +177,213 @@ impl Notifier {
         key_id: &str,
         team_id: &str,
         endpoint: Endpoint,
-        topic: &str,
+        topics: Topics,
     ) -> Result<Notifier, Error>
     where
         R: Read,
     {
-        let client = Client::token(pkcs8, key_id, team_id, endpoint)?;
+        let environment = Self::endpoint_name(&endpoint);
+        let client = ApnsClient::token(pkcs8, key_id, team_id, endpoint)?;
         let notifier_type = NotifierType::Token;
         TOKEN_CONSUMERS.inc();
 
         Ok(Notifier {
             client,
-            topic: String::from(topic),
+            topics,
             notifier_type,
+            environment,
+            sandbox: None,
         })
     }
 
-    pub fn notify(&self, event: &PushNotification) -> Timeout<FutureResponse> {
-        self.client.send_with_timeout(self.gen_payload(event), Duration::from_secs(3))
+    /// Sends the notifications again to the sandbox if production says the
+    /// device token is bad. Only for production notifiers.
+    pub fn with_sandbox_fallback(mut self, credentials: Credentials) -> Notifier {
+        if self.environment == "production" {
+            self.sandbox = Some(SandboxFallback {
+                credentials,
+                client: Mutex::new(None),
+            });
+        }
+
+        self
     }
 
-    fn gen_payload<'a>(&'a self, event: &'a PushNotification) -> Payload<'a> {
-        let notification_data = event.get_apple();
-        let headers = notification_data.get_headers();
+    /// `production` or `sandbox`.
+    pub fn environment(&self) -> &'static str {
+        self.environment
+    }
 
-        let mut options = NotificationOptions {
-            ..Default::default()
-        };
+    /// True if the sandbox fallback is enabled.
+    pub fn has_sandbox_fallback(&self) -> bool {
+        self.sandbox.is_some()
+    }
+
+    /// Sends the notification to the sandbox, `None` if the fallback is not
+    /// enabled or the client can't be built.
+    pub fn notify_sandbox(
+        &self,
+        event: &PushNotification,
+        push_type: PushType,
+    ) -> Option<FutureResponse> {
+        let sandbox = self.sandbox.as_ref()?;
+        let mut client = sandbox.client.lock().unwrap();
+
+        if client.is_none() {
+            match sandbox.credentials.client(Endpoint::Sandbox) {
+                Ok(sandbox_client) => {
+                    info!("Sandbox client created"; event);
+                    *client = Some(sandbox_client);
+                }
+                Err(e) => {
+                    error!("Couldn't create the sandbox client"; event, "error" => format!("{:?}", e));
+                    return None;
+                }
+            }
+        }
+
+        client.as_ref().map(|client| client.send(self.gen_request(event, push_type)))
+    }
+
+    pub fn notify(&self, event: &PushNotification, push_type: PushType) -> FutureResponse {
+        self.client.send(self.gen_request(event, push_type))
+    }
+
+    fn gen_request(&self, event: &PushNotification, push_type: PushType) -> ApnsRequest {
+        ApnsRequest {
+            device_token: event.get_device_token().to_string(),
+            headers: Self::gen_headers(event, push_type, &self.topics),
+            payload: Self::gen_payload(event),
+        }
+    }
+
+    fn gen_headers(
+        event: &PushNotification,
+        push_type: PushType,
+        topics: &Topics,
+    ) -> Vec<(&'static str, String)> {
+        let headers = event.get_apple().get_headers();
+        let mut request_headers = vec![("apns-push-type", push_type.as_str().to_string())];
 
         if headers.has_apns_priority() {
             match headers.get_apns_priority() {
-                10 => options.apns_priority = Priority::High,
-                _ => options.apns_priority = Priority::Normal,
+                10 => request_headers.push(("apns-priority", String::from("10"))),
+                _ => request_headers.push(("apns-priority", String::from("5"))),
             }
+        } else if let PushType::Background = push_type {
+            // APNs refuses background pushes with a high priority.
+            request_headers.push(("apns-priority", String::from("5")));
         }
         if event.get_header().has_correlation_id() {
-            options.apns_id = Some(event.get_header().get_correlation_id());
+            request_headers.push(("apns-id", event.get_header().get_correlation_id().to_string()));
         }
         if headers.has_apns_expiration() {
-            options.apns_expiration = Some(headers.get_apns_expiration() as u64);
+            request_headers.push(("apns-expiration", headers.get_apns_expiration().to_string()));
         }
         if headers.has_apns_topic() {
-            options.apns_topic = Some(headers.get_apns_topic());
+            request_headers.push(("apns-topic", headers.get_apns_topic().to_string()));
         } else {
-            options.apns_topic = Some(&self.topic);
+            request_headers.push(("apns-topic", topics.get(&push_type).to_string()));
+        }
+        if headers.has_apns_collapse_id() {
+            let collapse_id = headers.get_apns_collapse_id();
+
+            if collapse_id.len() > MAX_COLLAPSE_ID_LENGTH {
+                warn!(
+                    "Collapse id longer than 64 bytes, ignoring";
+                    event,
+                    "apns_collapse_id" => collapse_id
+                );
+            } else {
+                request_headers.push(("apns-collapse-id", collapse_id.to_string()));
+            }
         }
 
-        let mut payload = if notification_data.has_localized() {
+        request_headers
+    }
+
+    fn gen_payload(event: &PushNotification) -> Value {
+        let notification_data = event.get_apple();
+
+        let mut aps = if notification_data.has_live_activity() {
+            Self::live_activity_aps(event)
+        } else if notification_data.has_localized() {
             let alert_data = notification_data.get_localized();
-            let mut builder =
-                LocalizedNotificationBuilder::new(alert_data.get_title(), alert_data.get_body());
+            let mut alert = Map::new();
+
+            alert.insert("title".into(), json!(alert_data.get_title()));
+            alert.insert("body".into(), json!(alert_data.get_body()));
 
             if alert_data.has_title_loc_key() {
-                builder.set_title_loc_key(alert_data.get_title_loc_key());
+                alert.insert("title-loc-key".into(), json!(alert_data.get_title_loc_key()));
             }
             if !alert_data.get_title_loc_args().is_empty() {
-                builder.set_title_loc_args(&alert_data.get_title_loc_args());
+                alert.insert("title-loc-args".into(), json!(alert_data.get_title_loc_args()));
+            }
+            if alert_data.has_subtitle() {
+                alert.insert("subtitle".into(), json!(alert_data.get_subtitle()));
+            }
+            if alert_data.has_subtitle_loc_key() {
+                alert.insert("subtitle-loc-key".into(), json!(alert_data.get_subtitle_loc_key()));
+            }
+            if !alert_data.get_subtitle_loc_args().is_empty() {
+                alert.insert("subtitle-loc-args".into(), json!(alert_data.get_subtitle_loc_args()));
             }
             if alert_data.has_action_loc_key() {
-                builder.set_action_loc_key(alert_data.get_action_loc_key());
+                alert.insert("action-loc-key".into(), json!(alert_data.get_action_loc_key()));
             }
             if alert_data.has_launch_image() {
-                builder.set_launch_image(alert_data.get_launch_image());
+                alert.insert("launch-image".into(), json!(alert_data.get_launch_image()));
             }
             if alert_data.has_loc_key() {
-                builder.set_loc_key(alert_data.get_loc_key());
+                alert.insert("loc-key".into(), json!(alert_data.get_loc_key()));
             }
             if !alert_data.get_loc_args().is_empty() {
-                builder.set_loc_args(&alert_data.get_loc_args());
-            }
-            if notification_data.has_badge() {
-                builder.set_badge(notification_data.get_badge());
-            }
-            if notification_data.has_sound() {
-                builder.set_sound(notification_data.get_sound());
-            }
-            if notification_data.has_category() {
-                builder.set_category(notification_data.get_category());
+                alert.insert("loc-args".into(), json!(alert_data.get_loc_args()));
             }
+
+            let mut aps = Self::alert_aps(event, Value::Object(alert));
+
             if alert_data.has_mutable_content() && alert_data.get_mutable_content() {
-                builder.set_mutable_content();
+                aps.insert("mutable-content".into(), json!(1));
             }
 
-            builder.build(event.get_device_token(), options)
+            aps
         } else if notification_data.has_silent() {
-            SilentNotificationBuilder::new().build(event.get_device_token(), options)
+            let mut aps = Map::new();
+            aps.insert("content-available".into(), json!(1));
+            aps
         } else {
-            let mut builder = PlainNotificationBuilder::new(notification_data.get_plain());
+            Self::alert_aps(event, json!(notification_data.get_plain()))
+        };
 
-            if notification_data.has_badge() {
-                builder.set_badge(notification_data.get_badge());
-            }
-            if notification_data.has_sound() {
-                builder.set_sound(notification_data.get_sound());
-            }
-            if notification_data.has_category() {
-                builder.set_category(notification_data.get_category());
+        if notification_data.has_thread_id() {
+            aps.insert("thread-id".into(), json!(notification_data.get_thread_id()));
+        }
+        if notification_data.has_interruption_level() {
+            let level = notification_data.get_interruption_level();
+
+            match Self::interruption_level(level) {
+                Some(level) => {
+                    aps.insert("interruption-level".into(), json!(level));
+                }
+                None => warn!(
+                    "Unknown interruption level, ignoring";
+                    event,
+                    "interruption_level" => level
+                ),
             }
+        }
+        if notification_data.has_relevance_score() {
+            let score = f64::from(notification_data.get_relevance_score());
+            aps.insert("relevance-score".into(), json!(score.max(0.0).min(1.0)));
+        }
+        if notification_data.has_target_content_id() {
+            aps.insert("target-content-id".into(), json!(notification_data.get_target_content_id()));
+        }
 
-            builder.build(event.get_device_token(), options)
-        };
+        let mut payload = Map::new();
+        payload.insert("aps".into(), Value::Object(aps));
 
         if notification_data.has_custom_data() {
             let custom_data = notification_data.get_custom_data();
@@ -166,9 +391,11 @@ impl Notifier {
             let v: Result<Value, JsonError> = serde_json::from_str(custom_data.get_body());
             match v {
                 Ok(json) => {
-                    if let Err(e) = payload.add_custom_data(custom_data.get_key(), &json) {
-                        error!("Couldn't serialize custom data {:?}", e);
-                    };
+                    if custom_data.get_key() == "aps" {
+                        error!("Custom data can't use the aps key");
+                    } else {
+                        payload.insert(custom_data.get_key().to_string(), json);
+                    }
                 }
                 Err(e) => {
                     error!("Non-json custom data: {:?}", e);
@@ -176,6 +403,357 @@ impl Notifier {
             }
         }
 
-        payload
+        Value::Object(payload)
+    }
+
+    /// The `aps` of a notification with an alert, badge and sound.
+    fn alert_aps(event: &PushNotification, alert: Value) -> Map<String, Value> {
+        let notification_data = event.get_apple();
+        let mut aps = Map::new();
+
+        aps.insert("alert".into(), alert);
+
+        if notification_data.has_badge() {
+            aps.insert("badge".into(), json!(notification_data.get_badge()));
+        }
+        if notification_data.has_critical_sound() {
+            let sound = notification_data.get_critical_sound();
+            let mut critical = Map::new();
+
+            critical.insert("critical".into(), json!(1));
+            critical.insert("name".into(), json!(sound.get_name()));
+
+            if sound.has_volume() {
+                critical.insert("volume".into(), json!(Self::critical_volume(sound.get_volume())));
+            }
+
+            aps.insert("sound".into(), Value::Object(critical));
+        } else if notification_data.has_sound() {
+            aps.insert("sound".into(), json!(notification_data.get_sound()));
+        }
+        if notification_data.has_category() {
+            aps.insert("category".into(), json!(notification_data.get_category()));
+        }
+
+        aps
+    }
+
+    /// A Live Activity update or end, without an alert.
+    fn live_activity_aps(event: &PushNotification) -> Map<String, Value> {
+        let live_activity = event.get_apple().get_live_activity();
+        let mut aps = Map::new();
+
+        aps.insert("event".into(), json!(live_activity.get_event()));
+        aps.insert("timestamp".into(), json!(live_activity.get_timestamp()));
+
+        // Checked in `validate`.
+        if let Ok(json) = serde_json::from_str::<Value>(live_activity.get_content_state()) {
+            aps.insert("content-state".into(), json);
+        }
+
+        if live_activity.has_dismissal_date() {
+            aps.insert("dismissal-date".into(), json!(live_activity.get_dismissal_date()));
+        }
+        if live_activity.has_stale_date() {
+            aps.insert("stale-date".into(), json!(live_activity.get_stale_date()));
+        }
+
+        aps
+    }
+
+    /// An error with the reason if the notification can't be sent. A Live
+    /// Activity needs an `update` or `end` event, a positive timestamp and a
+    /// JSON content state.
+    pub fn validate(event: &PushNotification) -> Result<(), String> {
+        let notification_data = event.get_apple();
+
+        if !notification_data.has_live_activity() {
+            return Ok(());
+        }
+
+        let live_activity = notification_data.get_live_activity();
+
+        match live_activity.get_event() {
+            "update" | "end" => (),
+            other => return Err(format!("invalid_live_activity_event: {}", other)),
+        }
+
+        // APNs orders the updates by the timestamp, and drops the ones
+        // without.
+        if live_activity.get_timestamp() <= 0 {
+            return Err(format!("invalid_live_activity_timestamp: {}", live_activity.get_timestamp()));
+        }
+
+        serde_json::from_str::<Value>(live_activity.get_content_state())
+            .map(|_| ())
+            .map_err(|e| format!("invalid_content_state: {}", e))
+    }
+
+    fn endpoint_name(endpoint: &Endpoint) -> &'static str {
+        match *endpoint {
+            Endpoint::Production => "production",
+            Endpoint::Sandbox => "sandbox",
+        }
+    }
+
+    /// `alert`, `background`, `voip`, `complication`, `fileprovider`, `mdm`,
+    /// `location` or `liveactivity`.
+    pub fn parse_push_type(push_type: &str) -> Option<PushType> {
+        match push_type.to_lowercase().as_str() {
+            "alert" => Some(PushType::Alert),
+            "background" => Some(PushType::Background),
+            "voip" => Some(PushType::Voip),
+            "complication" => Some(PushType::Complication),
+            "fileprovider" => Some(PushType::FileProvider),
+            "mdm" => Some(PushType::Mdm),
+            "location" => Some(PushType::Location),
+            "liveactivity" => Some(PushType::LiveActivity),
+            _ => None,
+        }
+    }
+
+    /// `passive`, `active`, `time-sensitive` or `critical`.
+    fn interruption_level(level: &str) -> Option<&'static str> {
+        match level.to_lowercase().as_str() {
+            "passive" => Some("passive"),
+            "active" => Some("active"),
+            "time-sensitive" | "time_sensitive" => Some("time-sensitive"),
+            "critical" => Some("critical"),
+            _ => None,
+        }
+    }
+
+    /// Volume of a critical alert sound clamped between 0 and 1. Without a
+    /// volume APNs plays the sound at full volume.
+    fn critical_volume(volume: f32) -> f64 {
+        f64::from(volume).max(0.0).min(1.0)
+    }
+
+    /// The `apns-push-type` from the request. If not given or unknown,
+    /// `background` for silent notifications and `alert` for the rest. Live
+    /// Activities are always sent as `liveactivity`.
+    pub fn push_type(event: &PushNotification) -> PushType {
+        let notification_data = event.get_apple();
+        let headers = notification_data.get_headers();
+
+        if notification_data.has_live_activity() {
+            return PushType::LiveActivity;
+        }
+
+        if headers.has_apns_push_type() {
+            let push_type = Self::parse_push_type(headers.get_apns_push_type());
+
+            match push_type {
+                Some(push_type) => return push_type,
+                None => warn!(
+                    "Unknown apns-push-type, inferring from the payload";
+                    event,
+                    "apns_push_type" => headers.get_apns_push_type()
+                ),
+            }
+        }
+
+        if notification_data.has_silent() {
+            PushType::Background
+        } else {
+            PushType::Alert
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apple_event() -> PushNotification {
+        let mut event = PushNotification::new();
+        event.mut_apple();
+        event
+    }
+
+    fn header<'a>(headers: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|&&(key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    #[test]
+    fn infers_the_push_type_from_the_payload() {
+        let mut silent = apple_event();
+        silent.mut_apple().mut_silent();
+
+        assert_eq!(PushType::Alert, Notifier::push_type(&apple_event()));
+        assert_eq!(PushType::Background, Notifier::push_type(&silent));
+    }
+
+    #[test]
+    fn uses_the_push_type_of_the_request() {
+        let mut event = apple_event();
+        event.mut_apple().mut_headers().set_apns_push_type(String::from("VoIP"));
+
+        assert_eq!(PushType::Voip, Notifier::push_type(&event));
+
+        event.mut_apple().mut_headers().set_apns_push_type(String::from("unknown"));
+        event.mut_apple().mut_silent();
+
+        assert_eq!(PushType::Background, Notifier::push_type(&event));
+    }
+
+    #[test]
+    fn sends_live_activities_as_liveactivity() {
+        let mut event = apple_event();
+        event.mut_apple().mut_headers().set_apns_push_type(String::from("alert"));
+        event.mut_apple().mut_live_activity();
+
+        assert_eq!(PushType::LiveActivity, Notifier::push_type(&event));
+    }
+
+    #[test]
+    fn adds_the_suffix_of_the_push_type_to_the_bundle_topic() {
+        let topics = Topics::bundle("com.example");
+
+        assert_eq!("com.example", topics.get(&PushType::Alert));
+        assert_eq!("com.example", topics.get(&PushType::Background));
+        assert_eq!("com.example.voip", topics.get(&PushType::Voip));
+        assert_eq!("com.example.complication", topics.get(&PushType::Complication));
+        assert_eq!("com.example.pushkit.fileprovider", topics.get(&PushType::FileProvider));
+        assert_eq!("com.example.location-query", topics.get(&PushType::Location));
+        assert_eq!("com.example.push-type.liveactivity", topics.get(&PushType::LiveActivity));
+
+        assert_eq!("com.example.other", Topics::fixed("com.example.other").get(&PushType::Voip));
+    }
+
+    #[test]
+    fn sends_to_the_topic_of_the_push_type() {
+        let headers = Notifier::gen_headers(
+            &apple_event(),
+            PushType::Voip,
+            &Topics::bundle("com.example"),
+        );
+
+        assert_eq!(Some("voip"), header(&headers, "apns-push-type"));
+        assert_eq!(Some("com.example.voip"), header(&headers, "apns-topic"));
+    }
+
+    #[test]
+    fn drops_collapse_ids_over_64_bytes() {
+        let topics = Topics::bundle("com.example");
+        let mut event = apple_event();
+
+        event.mut_apple().mut_headers().set_apns_collapse_id("a".repeat(64));
+        let headers = Notifier::gen_headers(&event, PushType::Alert, &topics);
+
+        assert_eq!(Some("a".repeat(64).as_str()), header(&headers, "apns-collapse-id"));
+
+        event.mut_apple().mut_headers().set_apns_collapse_id("a".repeat(65));
+        let headers = Notifier::gen_headers(&event, PushType::Alert, &topics);
+
+        assert_eq!(None, header(&headers, "apns-collapse-id"));
+    }
+
+    #[test]
+    fn sends_background_pushes_with_priority_5() {
+        let topics = Topics::bundle("com.example");
+
+        let headers = Notifier::gen_headers(&apple_event(), PushType::Background, &topics);
+        assert_eq!(Some("5"), header(&headers, "apns-priority"));
+
+        let headers = Notifier::gen_headers(&apple_event(), PushType::Alert, &topics);
+        assert_eq!(None, header(&headers, "apns-priority"));
+    }
+
+    #[test]
+    fn clamps_the_relevance_score() {
+        let mut event = apple_event();
+
+        event.mut_apple().set_relevance_score(1.5);
+        assert_eq!(json!(1.0), Notifier::gen_payload(&event)["aps"]["relevance-score"]);
+
+        event.mut_apple().set_relevance_score(-0.5);
+        assert_eq!(json!(0.0), Notifier::gen_payload(&event)["aps"]["relevance-score"]);
+    }
+
+    #[test]
+    fn clamps_the_critical_volume() {
+        assert_eq!(1.0, Notifier::critical_volume(1.5));
+        assert_eq!(0.0, Notifier::critical_volume(-0.5));
+        assert_eq!(0.5, Notifier::critical_volume(0.5));
+    }
+
+    #[test]
+    fn keeps_a_zero_critical_volume() {
+        let mut event = apple_event();
+
+        {
+            let sound = event.mut_apple().mut_critical_sound();
+            sound.set_name(String::from("alarm.caf"));
+            sound.set_volume(0.0);
+        }
+
+        let payload = Notifier::gen_payload(&event);
+
+        assert_eq!(
+            json!({ "critical": 1, "name": "alarm.caf", "volume": 0.0 }),
+            payload["aps"]["sound"]
+        );
+    }
+
+    #[test]
+    fn sends_the_live_activity_without_an_alert() {
+        let mut event = apple_event();
+
+        {
+            let live_activity = event.mut_apple().mut_live_activity();
+            live_activity.set_event(String::from("update"));
+            live_activity.set_timestamp(1_700_000_000);
+            live_activity.set_content_state(String::from(r#"{"score":"2-1"}"#));
+            live_activity.set_stale_date(1_700_003_600);
+        }
+
+        let payload = Notifier::gen_payload(&event);
+
+        assert_eq!(
+            json!({
+                "aps": {
+                    "event": "update",
+                    "timestamp": 1_700_000_000,
+                    "content-state": { "score": "2-1" },
+                    "stale-date": 1_700_003_600
+                }
+            }),
+            payload
+        );
+    }
+
+    fn live_activity(timestamp: i64) -> PushNotification {
+        let mut event = apple_event();
+
+        {
+            let live_activity = event.mut_apple().mut_live_activity();
+            live_activity.set_event(String::from("end"));
+            live_activity.set_timestamp(timestamp);
+            live_activity.set_content_state(String::from("{}"));
+        }
+
+        event
+    }
+
+    #[test]
+    fn accepts_a_live_activity_with_a_timestamp() {
+        assert_eq!(Ok(()), Notifier::validate(&live_activity(1_700_000_000)));
+    }
+
+    #[test]
+    fn rejects_a_live_activity_without_a_positive_timestamp() {
+        assert_eq!(
+            Err(String::from("invalid_live_activity_timestamp: 0")),
+            Notifier::validate(&live_activity(0))
+        );
+
+        assert_eq!(
+            Err(String::from("invalid_live_activity_timestamp: -1")),
+            Notifier::validate(&live_activity(-1))
+        );
     }
 }
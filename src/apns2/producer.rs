@@ -6,20 +6,20 @@ use a2::{
 
 use common::{
     events::{
-        push_result::{
-            PushResult,
-            PushResult_ResponseAction as ResponseAction
-        },
+        push_result::PushResult_ResponseAction as ResponseAction,
         push_notification::PushNotification,
     },
     kafka::{
-        DeliveryFuture,
-        ResponseProducer
+        ResponseProducer,
+        Routing,
     },
-    metrics::*
+    metrics::*,
+    tenants::TENANTS,
+    transport::{Delivery, Transport},
 };
 
 use heck::SnakeCase;
+use std::sync::Arc;
 use CONFIG;
 
 pub struct ApnsProducer {
@@ -27,47 +27,56 @@ pub struct ApnsProducer {
 }
 
 impl ApnsProducer {
-    pub fn new() -> ApnsProducer {
+    pub fn new(transport: Arc<Transport>) -> ApnsProducer {
         ApnsProducer {
-            producer: ResponseProducer::new(&CONFIG.kafka),
+            producer: ResponseProducer::new(&CONFIG.kafka, transport),
         }
     }
 
     pub fn handle_ok(
         &self,
-        key: Option<Vec<u8>>,
-        event: PushNotification
-    ) -> DeliveryFuture
+        routing: Routing,
+        event: PushNotification,
+        environment: &'static str,
+    ) -> Delivery
     {
         CALLBACKS_COUNTER.with_label_values(&["success"]).inc();
+        TENANTS.success(event.get_universe());
 
         info!(
             "Successfully sent a push notification";
             &event,
-            "successful" => true
+            "successful" => true,
+            "environment" => environment
         );
 
-        let result: PushResult = (event, ResponseAction::None).into();
-        self.producer.publish(key, &result)
+        self.respond(routing, event, ResponseAction::None, environment)
     }
 
     pub fn handle_err(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
-        response: Response
-    ) -> DeliveryFuture
+        response: Response,
+        environment: &'static str,
+    ) -> Delivery
     {
         let reason = response.error.as_ref()
             .map(|ref error| {
                 format!("{:?}", error.reason)
             });
 
+        TENANTS.error(
+            event.get_universe(),
+            reason.as_ref().map(|r| r.as_str()).unwrap_or("Unknown"),
+        );
+
         error!(
             "Error sending a push notification";
             &event,
             "successful" => false,
-            "reason" => reason
+            "reason" => reason,
+            "environment" => environment
         );
 
         let response_action =
@@ -88,22 +97,56 @@ impl ApnsProducer {
                 ResponseAction::None
             };
 
-        let result: PushResult = (event, response_action).into();
-        self.producer.publish(key, &result)
+        self.respond(routing, event, response_action, environment)
     }
 
     pub fn handle_fatal(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
         error: Error
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
         let status_label = format!("{:?}", error).to_snake_case();
-        let result: PushResult = (event, ResponseAction::Retry).into();
 
+        TENANTS.error(event.get_universe(), &status_label);
         CALLBACKS_COUNTER.with_label_values(&[&status_label]).inc();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, ResponseAction::Retry)
+    }
+
+    /// Responds to a notification we can't send to APNs, without retrying.
+    pub fn handle_invalid(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        reason: &str,
+    ) -> Delivery
+    {
+        TENANTS.error(event.get_universe(), "invalid_notification");
+        CALLBACKS_COUNTER.with_label_values(&["invalid_notification"]).inc();
+
+        error!(
+            "Invalid push notification, not sending";
+            &event,
+            "successful" => false,
+            "reason" => reason
+        );
+
+        self.producer.respond(routing, event, ResponseAction::None)
+    }
+
+    /// Responds with the APNs environment the device token was sent to.
+    fn respond(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        response_action: ResponseAction,
+        environment: &'static str,
+    ) -> Delivery
+    {
+        self.producer.respond_with(routing, event, response_action, |result| {
+            result.set_environment(environment.to_string());
+        })
     }
 }
 
@@ -1,28 +1,569 @@
+use argparse::{ArgumentParser, StoreTrue};
 use kafka;
-use toml;
+use toml::{Value, value::Table};
+use transport::TransportType;
 
-use std::{fs::File, io::prelude::*};
+use std::{
+    env,
+    fs::File,
+    io::{prelude::*, ErrorKind},
+    process,
+};
+
+/// Environment variables starting with the prefix override the values from
+/// the configuration file.
+const ENV_PREFIX: &str = "XORC_";
+
+/// Separates the sections and the key in the variable name, e.g.
+/// `XORC_KAFKA__BROKERS` sets `brokers` in the `[kafka]` section.
+const ENV_SEPARATOR: &str = "__";
+
+/// The sections the environment can override. Other variables with the
+/// prefix, such as `XORC_APNS2_SERVICE_HOST` from Kubernetes, are ignored.
+const ENV_SECTIONS: &[&str] = &["kafka"];
+
+/// Keys of the sections not used anymore, ignored with a warning.
+const DEPRECATED_KEYS: &[(&str, &str, &str)] = &[(
+    "kafka",
+    "consumer_type",
+    "the request type is read from the rpc header",
+)];
+
+/// The sections of the configuration file.
+const KEYS: &[Key] = &[
+    Key { name: "kafka", kind: Kind::Table(kafka::KEYS), required: true },
+    Key { name: "transport", kind: Kind::Choice(&["kafka", "stdio"]), required: false },
+];
+
+/// The type of a configuration value, checked before deserializing and used
+/// to parse the environment overrides.
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    String,
+    /// One of the strings.
+    Choice(&'static [&'static str]),
+    /// A non-negative integer.
+    Integer,
+    /// A number, integers included.
+    Float,
+    Boolean,
+    /// An array of strings.
+    Strings,
+    /// A table with the keys.
+    Table(&'static [Key]),
+    /// An array of tables with the keys.
+    Tables(&'static [Key]),
+    /// A table of strings with any keys.
+    StringMap,
+    /// A table of tables with the keys, under any keys.
+    TableMap(&'static [Key]),
+}
+
+/// A key in a table of the configuration.
+#[derive(Debug)]
+pub struct Key {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub required: bool,
+}
 
 #[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub kafka: kafka::Config,
+    /// Where the requests come from. Default: `kafka`.
+    #[serde(default)]
+    pub transport: TransportType,
 }
 
 impl Config {
-    /// Load TOML-formatted configuration from `path`.
+    /// Load TOML-formatted configuration from `path`, applying the overrides
+    /// from the environment. Exits the process with a report of all the
+    /// errors if the configuration is invalid.
     pub fn parse(path: &str) -> Config {
+        match Self::load(path) {
+            Ok(config) => config,
+            Err(errors) => {
+                eprintln!("{}", Self::report(path, &errors));
+                process::exit(1);
+            }
+        }
+    }
+
+    /// Validates the configuration in `path`, prints the result and exits,
+    /// non-zero if the configuration is invalid.
+    pub fn check(path: &str) -> ! {
+        match Self::load(path) {
+            Ok(_) => {
+                println!("Configuration in {} is valid", path);
+                process::exit(0);
+            }
+            Err(errors) => {
+                eprintln!("{}", Self::report(path, &errors));
+                process::exit(1);
+            }
+        }
+    }
+
+    /// Reads the file and the environment overrides, collecting every error
+    /// found on the way. The keys are checked for their presence and types
+    /// before deserializing, the values the types can't enforce are checked
+    /// with `validate` after that.
+    pub fn load(path: &str) -> Result<Config, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut missing = false;
+
+        let mut root = match Self::read(path) {
+            Ok(Some(root)) => root,
+            Ok(None) => {
+                missing = true;
+                Value::Table(Table::new())
+            }
+            Err(error) => {
+                errors.push(error);
+                Value::Table(Table::new())
+            }
+        };
+
+        apply_env_overrides(&mut root, &mut errors);
+
+        for warning in remove_deprecated(&mut root) {
+            eprintln!("Warning: {}", warning);
+        }
+
+        let type_errors = check_table(&root, KEYS, "");
+
+        if !type_errors.is_empty() {
+            if missing {
+                errors.push(format!(
+                    "config file {} not found, only the environment was used",
+                    path
+                ));
+            }
+
+            errors.extend(type_errors);
+            return Err(errors);
+        }
+
+        match root.try_into::<Config>() {
+            Ok(config) => {
+                errors.extend(config.kafka.validate());
+
+                if errors.is_empty() {
+                    Ok(config)
+                } else {
+                    Err(errors)
+                }
+            }
+            Err(error) => {
+                if missing {
+                    errors.push(format!(
+                        "config file {} not found, only the environment was used",
+                        path
+                    ));
+                }
+
+                errors.push(format!("{}", error));
+                Err(errors)
+            }
+        }
+    }
+
+    /// A missing file is not an error, the whole configuration can come from
+    /// the environment.
+    fn read(path: &str) -> Result<Option<Value>, String> {
         let mut config_toml = String::new();
 
         let mut file = match File::open(path) {
             Ok(file) => file,
-            Err(err) => {
-                panic!("Error while reading config file: [{}]", err);
-            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(format!("error opening config file {}: {}", path, err)),
         };
 
         file.read_to_string(&mut config_toml)
-            .unwrap_or_else(|err| panic!("Error while reading config: [{}]", err));
+            .map_err(|err| format!("error reading config file {}: {}", path, err))?;
+
+        config_toml
+            .parse::<Value>()
+            .map(Some)
+            .map_err(|err| format!("invalid TOML in {}: {}", path, err))
+    }
+
+    fn report(path: &str, errors: &[String]) -> String {
+        let mut report = format!("Invalid configuration in {}:", path);
+
+        for error in errors {
+            report.push_str("\n  - ");
+            report.push_str(error);
+        }
+
+        report
+    }
+}
+
+/// Command line options shared by all the consumers.
+pub struct Options {
+    /// Only validate the configuration and exit.
+    pub check_config: bool,
+}
+
+impl Options {
+    pub fn parse(description: &str) -> Options {
+        let mut check_config = false;
+
+        {
+            let mut parser = ArgumentParser::new();
+            parser.set_description(description);
+
+            parser.refer(&mut check_config).add_option(
+                &["--check-config"],
+                StoreTrue,
+                "Validate the configuration, report the errors and exit",
+            );
+
+            parser.parse_args_or_exit();
+        }
+
+        Options { check_config }
+    }
+}
+
+/// The configuration file location from the `CONFIG` environment variable,
+/// or `default` if not set.
+pub fn location(default: &str) -> String {
+    env::var("CONFIG").unwrap_or_else(|_| default.to_string())
+}
+
+fn apply_env_overrides(root: &mut Value, errors: &mut Vec<String>) {
+    let mut variables: Vec<(String, String)> = env::vars()
+        .filter(|&(ref name, _)| is_override(name))
+        .collect();
+
+    variables.sort();
+
+    for (name, raw) in variables {
+        let path: Vec<String> = name[ENV_PREFIX.len()..]
+            .split(ENV_SEPARATOR)
+            .map(|key| key.to_lowercase())
+            .collect();
+
+        if path.iter().any(|key| key.is_empty()) {
+            errors.push(format!("{}: invalid variable name", name));
+            continue;
+        }
+
+        let result = match *root {
+            Value::Table(ref mut table) => override_value(table, &path, &raw),
+            _ => Err(String::from("the configuration is not a table")),
+        };
+
+        if let Err(error) = result {
+            errors.push(format!("{}: {}", name, error));
+        }
+    }
+}
+
+/// True for `XORC_<SECTION>__<KEY>` variables of a known section.
+fn is_override(name: &str) -> bool {
+    if !name.starts_with(ENV_PREFIX) {
+        return false;
+    }
+
+    let name = &name[ENV_PREFIX.len()..];
+
+    match name.find(ENV_SEPARATOR) {
+        Some(end) => ENV_SECTIONS.contains(&name[..end].to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+fn override_value(table: &mut Table, path: &[String], raw: &str) -> Result<(), String> {
+    let value = match kind_of(KEYS, path) {
+        Some(kind) => parse_value(kind, raw)?,
+        None => Value::String(raw.to_string()),
+    };
+
+    insert_value(table, path, value)
+}
+
+fn insert_value(table: &mut Table, path: &[String], value: Value) -> Result<(), String> {
+    let (key, rest) = path.split_first().unwrap();
+
+    if rest.is_empty() {
+        table.insert(key.clone(), value);
+        return Ok(());
+    }
+
+    let entry = table
+        .entry(key.clone())
+        .or_insert_with(|| Value::Table(Table::new()));
+
+    match *entry {
+        Value::Table(ref mut nested) => insert_value(nested, rest, value),
+        _ => Err(format!("`{}` is not a section", key)),
+    }
+}
+
+/// Removes the deprecated keys, returning a warning for every key found.
+fn remove_deprecated(root: &mut Value) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    for &(section, key, reason) in DEPRECATED_KEYS {
+        let removed = root
+            .get_mut(section)
+            .and_then(|section| section.as_table_mut())
+            .and_then(|table| table.remove(key));
+
+        if removed.is_some() {
+            warnings.push(format!("{}.{} is deprecated and ignored, {}", section, key, reason));
+        }
+    }
+
+    warnings
+}
+
+/// The type of the key at `path`, `None` for unknown keys.
+fn kind_of(keys: &[Key], path: &[String]) -> Option<Kind> {
+    let (name, rest) = path.split_first()?;
+    let kind = keys.iter().find(|key| key.name == name.as_str())?.kind;
+
+    match (kind, rest.len()) {
+        (kind, 0) => Some(kind),
+        (Kind::Table(keys), _) => kind_of(keys, rest),
+        (Kind::StringMap, 1) => Some(Kind::String),
+        (Kind::TableMap(keys), _) => kind_of(keys, &rest[1..]),
+        _ => None,
+    }
+}
+
+/// Parses the value of an environment variable as the type of its key.
+/// Strings are taken as-is, arrays of strings can be comma-separated and
+/// tables are parsed as TOML.
+fn parse_value(kind: Kind, raw: &str) -> Result<Value, String> {
+    let value = match kind {
+        Kind::String | Kind::Choice(_) => Value::String(raw.to_string()),
+        Kind::Integer => raw
+            .trim()
+            .parse::<i64>()
+            .map(Value::Integer)
+            .map_err(|_| format!("expected an integer, got `{}`", raw))?,
+        Kind::Float => raw
+            .trim()
+            .parse::<f64>()
+            .map(Value::Float)
+            .map_err(|_| format!("expected a number, got `{}`", raw))?,
+        Kind::Boolean => raw
+            .trim()
+            .parse::<bool>()
+            .map(Value::Boolean)
+            .map_err(|_| format!("expected true or false, got `{}`", raw))?,
+        Kind::Strings if !raw.trim_start().starts_with('[') => Value::Array(
+            raw.split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => literal(raw).ok_or_else(|| format!("invalid TOML value `{}`", raw))?,
+    };
+
+    Ok(value)
+}
+
+/// Parses the value as TOML, `None` if invalid.
+fn literal(raw: &str) -> Option<Value> {
+    format!("value = {}", raw)
+        .parse::<Value>()
+        .ok()
+        .and_then(|mut document| {
+            document
+                .as_table_mut()
+                .and_then(|table| table.remove("value"))
+        })
+}
+
+/// Checks the keys of the table against `keys`, returning every missing,
+/// unknown and mistyped key. `prefix` is the path of the table for the
+/// errors.
+fn check_table(value: &Value, keys: &[Key], prefix: &str) -> Vec<String> {
+    let table = match *value {
+        Value::Table(ref table) => table,
+        _ => return vec![format!("{}: expected a table", prefix.trim_end_matches('.'))],
+    };
+
+    let mut errors = Vec::new();
+
+    for key in keys {
+        if key.required && !table.contains_key(key.name) {
+            errors.push(format!("{}{}: missing", prefix, key.name));
+        }
+    }
+
+    for (name, value) in table.iter() {
+        let path = format!("{}{}", prefix, name);
+
+        match keys.iter().find(|key| key.name == name.as_str()) {
+            Some(key) => errors.extend(check_value(value, key.kind, &path)),
+            None => errors.push(format!("{}: unknown key", path)),
+        }
+    }
+
+    errors
+}
+
+fn check_value(value: &Value, kind: Kind, path: &str) -> Vec<String> {
+    let expected = match (kind, value) {
+        (Kind::String, &Value::String(_)) => return Vec::new(),
+        (Kind::Choice(choices), &Value::String(ref choice)) => {
+            if choices.contains(&choice.as_str()) {
+                return Vec::new();
+            }
+
+            format!("one of {}", choices.join(", "))
+        }
+        (Kind::Integer, &Value::Integer(i)) if i >= 0 => return Vec::new(),
+        (Kind::Float, &Value::Integer(_)) | (Kind::Float, &Value::Float(_)) => return Vec::new(),
+        (Kind::Boolean, &Value::Boolean(_)) => return Vec::new(),
+        (Kind::Strings, &Value::Array(ref items)) if items.iter().all(Value::is_str) => {
+            return Vec::new();
+        }
+        (Kind::StringMap, &Value::Table(ref table)) if table.values().all(Value::is_str) => {
+            return Vec::new();
+        }
+        (Kind::Table(keys), _) => return check_table(value, keys, &format!("{}.", path)),
+        (Kind::Tables(keys), &Value::Array(ref items)) => {
+            return items
+                .iter()
+                .enumerate()
+                .flat_map(|(i, item)| check_table(item, keys, &format!("{}[{}].", path, i)))
+                .collect();
+        }
+        (Kind::TableMap(keys), &Value::Table(ref table)) => {
+            return table
+                .iter()
+                .flat_map(|(name, item)| check_table(item, keys, &format!("{}.{}.", path, name)))
+                .collect();
+        }
+        (Kind::String, _) => String::from("a string"),
+        (Kind::Choice(choices), _) => format!("one of {}", choices.join(", ")),
+        (Kind::Integer, _) => String::from("a non-negative integer"),
+        (Kind::Float, _) => String::from("a number"),
+        (Kind::Boolean, _) => String::from("true or false"),
+        (Kind::Strings, _) => String::from("an array of strings"),
+        (Kind::Tables(_), _) => String::from("an array of tables"),
+        (Kind::StringMap, _) => String::from("a table of strings"),
+        (Kind::TableMap(_), _) => String::from("a table of tables"),
+    };
+
+    vec![format!("{}: expected {}, got {}", path, expected, value)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(toml: &str) -> Value {
+        toml.parse::<Value>().unwrap()
+    }
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn reports_all_missing_and_mistyped_keys() {
+        let root = parse(r#"
+            [kafka]
+            input_topic   = "input"
+            output_topic  = 1
+            group_id      = "group"
+            brokers       = "kafka:9092"
+            max_in_flight = "many"
+            offset_commit = "never"
+            unknown       = true
+
+            [[kafka.retry_topics]]
+            topic = "retry"
+        "#);
+
+        let mut errors = check_table(&root, KEYS, "");
+        errors.sort();
+
+        assert_eq!(
+            vec![
+                "kafka.config_topic: missing",
+                "kafka.max_in_flight: expected a non-negative integer, got \"many\"",
+                "kafka.offset_commit: expected one of auto, after_delivery, got \"never\"",
+                "kafka.output_topic: expected a string, got 1",
+                "kafka.retry_topics[0].delay_ms: missing",
+                "kafka.unknown: unknown key",
+            ],
+            errors
+        );
+    }
+
+    #[test]
+    fn accepts_a_valid_configuration() {
+        let root = parse(r#"
+            transport = "stdio"
+
+            [kafka]
+            input_topic  = "input"
+            config_topic = "config"
+            output_topic = "output"
+            group_id     = "group"
+            brokers      = "kafka:9092"
+            reply_topics = ["replies"]
+            consumer     = { "session.timeout.ms" = "30000" }
+
+            [kafka.rate_limits]
+            default = { per_second = 10 }
+
+            [kafka.rate_limits.universes]
+            tenant = { per_second = 1.5, burst = 3.0 }
+        "#);
+
+        assert!(check_table(&root, KEYS, "").is_empty());
+    }
+
+    #[test]
+    fn ignores_deprecated_keys() {
+        let mut root = parse(r#"
+            [kafka]
+            input_topic   = "input"
+            config_topic  = "config"
+            output_topic  = "output"
+            group_id      = "group"
+            brokers       = "kafka:9092"
+            consumer_type = "push_notification"
+        "#);
+
+        assert_eq!(1, remove_deprecated(&mut root).len());
+        assert!(check_table(&root, KEYS, "").is_empty());
+    }
+
+    #[test]
+    fn parses_overrides_as_the_type_of_the_key() {
+        let mut root = Table::new();
+
+        override_value(&mut root, &path(&["kafka", "sasl_password"]), "123456").unwrap();
+        override_value(&mut root, &path(&["kafka", "max_in_flight"]), "1000").unwrap();
+        override_value(&mut root, &path(&["kafka", "reply_topics"]), "a, b").unwrap();
+        override_value(&mut root, &path(&["kafka", "consumer", "fetch.min.bytes"]), "1").unwrap();
+
+        let kafka = root["kafka"].as_table().unwrap();
+
+        assert_eq!(Some("123456"), kafka["sasl_password"].as_str());
+        assert_eq!(Some(1000), kafka["max_in_flight"].as_integer());
+        assert_eq!(parse("value = [\"a\", \"b\"]")["value"], kafka["reply_topics"]);
+        assert_eq!(Some("1"), kafka["consumer"]["fetch.min.bytes"].as_str());
+    }
+
+    #[test]
+    fn rejects_overrides_not_matching_the_type() {
+        let mut root = Table::new();
 
-        toml::from_str(&config_toml).unwrap()
+        assert!(override_value(&mut root, &path(&["kafka", "max_in_flight"]), "lots").is_err());
+        assert!(override_value(&mut root, &path(&["kafka", "dedupe", "ttl_ms"]), "-").is_err());
     }
 }
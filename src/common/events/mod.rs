@@ -34,6 +34,45 @@ impl Into<PushResult> for (PushNotification, ResponseAction) {
     }
 }
 
+impl PushNotification {
+    /// Time in milliseconds since the epoch after which the notification is
+    /// not worth sending anymore. The APNs expiration is absolute, the FCM
+    /// and web push TTLs count from the creation of the request. `None` if
+    /// the notification doesn't expire. A zero expiration or TTL asks the
+    /// service to deliver now or drop it, and is left for the service.
+    pub fn expires_at(&self) -> Option<i64> {
+        if self.has_apple() {
+            let headers = self.get_apple().get_headers();
+
+            if headers.has_apns_expiration() && headers.get_apns_expiration() > 0 {
+                return Some(headers.get_apns_expiration() as i64 * 1000);
+            }
+
+            return None;
+        }
+
+        let ttl = if self.has_google() && self.get_google().has_time_to_live() {
+            self.get_google().get_time_to_live() as i64
+        } else if self.has_web() && self.get_web().has_ttl() {
+            self.get_web().get_ttl() as i64
+        } else {
+            return None;
+        };
+
+        let header = self.get_header();
+
+        if ttl <= 0 || !header.has_created_at() {
+            return None;
+        }
+
+        let created_at = header.get_created_at();
+        let created_at_ms =
+            created_at.get_seconds() * 1000 + i64::from(created_at.get_nanos() / 1_000_000);
+
+        Some(created_at_ms + ttl * 1000)
+    }
+}
+
 impl AsRef<str> for HttpRequest_HttpVerb {
     fn as_ref(&self) -> &str {
         match self {
@@ -46,3 +85,48 @@ impl AsRef<str> for HttpRequest_HttpVerb {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn created_at(seconds: i64) -> PushNotification {
+        let mut event = PushNotification::new();
+        event.mut_header().mut_created_at().set_seconds(seconds);
+        event
+    }
+
+    #[test]
+    fn expires_the_ttl_after_the_creation() {
+        let mut event = created_at(1_000);
+        event.mut_google().set_time_to_live(60);
+
+        assert_eq!(Some(1_060_000), event.expires_at());
+
+        let mut event = created_at(1_000);
+        event.mut_web().set_ttl(60);
+
+        assert_eq!(Some(1_060_000), event.expires_at());
+    }
+
+    #[test]
+    fn leaves_a_zero_ttl_to_the_service() {
+        let mut event = created_at(1_000);
+        event.mut_google().set_time_to_live(0);
+
+        assert_eq!(None, event.expires_at());
+
+        let mut event = created_at(1_000);
+        event.mut_web().set_ttl(0);
+
+        assert_eq!(None, event.expires_at());
+    }
+
+    #[test]
+    fn leaves_a_zero_apns_expiration_to_the_service() {
+        let mut event = created_at(1_000);
+        event.mut_apple().mut_headers().set_apns_expiration(0);
+
+        assert_eq!(None, event.expires_at());
+    }
+}
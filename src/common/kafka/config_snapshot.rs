@@ -0,0 +1,329 @@
+use protobuf::{CodedInputStream, CodedOutputStream, ProtobufResult};
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+
+/// Bumped when the file format changes, older snapshots are ignored.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The tenant configuration read from the config topic, with the offsets to
+/// continue from. Stored to a local file so a restart doesn't need to replay
+/// the whole topic.
+///
+/// The file is a sequence of protobuf-encoded fields: the version, the config
+/// topic, the number of partitions followed by the partition and offset
+/// pairs, and the partition, universe and `Application` payload of every
+/// tenant until the end of the file.
+pub struct ConfigSnapshot {
+    offsets: HashMap<i32, i64>,
+    applications: HashMap<String, (i32, Vec<u8>)>,
+    changed: bool,
+}
+
+impl ConfigSnapshot {
+    pub fn new() -> ConfigSnapshot {
+        ConfigSnapshot {
+            offsets: HashMap::new(),
+            applications: HashMap::new(),
+            changed: false,
+        }
+    }
+
+    /// Reads the snapshot of the config topic, `None` if not found or not
+    /// usable.
+    pub fn load(path: &str, config_topic: &str) -> Option<ConfigSnapshot> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                info!("No config snapshot found, replaying the config topic"; "path" => path);
+                return None;
+            }
+            Err(e) => {
+                warn!("Couldn't open the config snapshot"; "path" => path, "error" => format!("{:?}", e));
+                return None;
+            }
+        };
+
+        let mut reader = BufReader::new(file);
+
+        match Self::read(&mut CodedInputStream::new(&mut reader), config_topic) {
+            Ok(Some(snapshot)) => {
+                info!(
+                    "Config snapshot loaded";
+                    "path" => path,
+                    "applications" => snapshot.applications.len(),
+                    "offsets" => format!("{:?}", snapshot.offsets)
+                );
+
+                Some(snapshot)
+            }
+            Ok(None) => {
+                warn!("Config snapshot is for another topic or version, ignoring"; "path" => path);
+                None
+            }
+            Err(e) => {
+                warn!("Invalid config snapshot, ignoring"; "path" => path, "error" => format!("{:?}", e));
+                None
+            }
+        }
+    }
+
+    /// Writes the snapshot if changed since the last time. The file is
+    /// replaced only when fully written, and the directory synced for the
+    /// rename to survive a crash. The tenant configuration has credentials,
+    /// so only the owner can read the file.
+    pub fn save(&mut self, path: &str, config_topic: &str) {
+        if !self.changed {
+            return;
+        }
+
+        let temporary = format!("{}.tmp", path);
+
+        // A leftover file would keep its permissions.
+        let written = Self::remove_leftover(&temporary)
+            .and_then(|()| {
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&temporary)
+            })
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+
+                self.write(&mut CodedOutputStream::new(&mut writer), config_topic)
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))?;
+
+                writer.flush()?;
+                writer.get_ref().sync_all()
+            })
+            .and_then(|()| fs::rename(&temporary, path))
+            .and_then(|()| Self::sync_directory(path));
+
+        match written {
+            Ok(()) => {
+                debug!("Config snapshot written"; "path" => path);
+                self.changed = false;
+            }
+            Err(e) => {
+                warn!("Couldn't write the config snapshot"; "path" => path, "error" => format!("{:?}", e));
+            }
+        }
+    }
+
+    fn remove_leftover(path: &str) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    fn sync_directory(path: &str) -> io::Result<()> {
+        let directory = match Path::new(path).parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+
+        File::open(directory)?.sync_all()
+    }
+
+    /// The offset to continue the partition from.
+    pub fn offset(&self, partition: i32) -> Option<i64> {
+        self.offsets.get(&partition).cloned()
+    }
+
+    /// Forgets the partition, to be replayed from the beginning.
+    pub fn reset_partition(&mut self, partition: i32) {
+        self.offsets.remove(&partition);
+        self.applications.retain(|_, &mut (p, _)| p != partition);
+        self.changed = true;
+    }
+
+    /// The stored tenants with their partition and `Application` payload.
+    pub fn applications(&self) -> Vec<(String, i32, Vec<u8>)> {
+        self.applications
+            .iter()
+            .map(|(universe, &(partition, ref payload))| {
+                (universe.clone(), partition, payload.clone())
+            })
+            .collect()
+    }
+
+    /// Stores the tenant configuration, removing the tenant if `None`.
+    pub fn set(&mut self, universe: &str, partition: i32, payload: Option<&[u8]>) {
+        match payload {
+            Some(payload) => {
+                self.applications
+                    .insert(universe.to_string(), (partition, payload.to_vec()));
+            }
+            None => {
+                self.applications.remove(universe);
+            }
+        }
+
+        self.changed = true;
+    }
+
+    /// Marks the message in the partition processed.
+    pub fn processed(&mut self, partition: i32, offset: i64) {
+        self.offsets.insert(partition, offset + 1);
+        self.changed = true;
+    }
+
+    fn read(
+        input: &mut CodedInputStream,
+        config_topic: &str,
+    ) -> ProtobufResult<Option<ConfigSnapshot>> {
+        if input.read_uint32()? != SNAPSHOT_VERSION {
+            return Ok(None);
+        }
+
+        if input.read_string()? != config_topic {
+            return Ok(None);
+        }
+
+        let mut snapshot = ConfigSnapshot::new();
+
+        for _ in 0..input.read_uint32()? {
+            let partition = input.read_int32()?;
+            let offset = input.read_int64()?;
+
+            snapshot.offsets.insert(partition, offset);
+        }
+
+        while !input.eof()? {
+            let partition = input.read_int32()?;
+            let universe = input.read_string()?;
+            let payload = input.read_bytes()?;
+
+            snapshot.applications.insert(universe, (partition, payload));
+        }
+
+        Ok(Some(snapshot))
+    }
+
+    fn write(&self, output: &mut CodedOutputStream, config_topic: &str) -> ProtobufResult<()> {
+        output.write_uint32_no_tag(SNAPSHOT_VERSION)?;
+        output.write_string_no_tag(config_topic)?;
+        output.write_uint32_no_tag(self.offsets.len() as u32)?;
+
+        for (partition, offset) in self.offsets.iter() {
+            output.write_int32_no_tag(*partition)?;
+            output.write_int64_no_tag(*offset)?;
+        }
+
+        for (universe, &(partition, ref payload)) in self.applications.iter() {
+            output.write_int32_no_tag(partition)?;
+            output.write_string_no_tag(universe)?;
+            output.write_bytes_no_tag(payload)?;
+        }
+
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("config_snapshot_{}_{}", name, process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn loads_the_saved_snapshot() {
+        let path = path("round_trip");
+
+        let mut snapshot = ConfigSnapshot::new();
+        snapshot.set("first", 0, Some(b"one"));
+        snapshot.set("second", 1, Some(b"two"));
+        snapshot.set("removed", 1, Some(b"three"));
+        snapshot.set("removed", 1, None);
+        snapshot.processed(0, 41);
+        snapshot.processed(1, 9);
+        snapshot.save(&path, "applications");
+
+        let loaded = ConfigSnapshot::load(&path, "applications").unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut applications = loaded.applications();
+        applications.sort();
+
+        assert_eq!(
+            vec![
+                ("first".to_string(), 0, b"one".to_vec()),
+                ("second".to_string(), 1, b"two".to_vec()),
+            ],
+            applications
+        );
+
+        assert_eq!(Some(42), loaded.offset(0));
+        assert_eq!(Some(10), loaded.offset(1));
+        assert_eq!(None, loaded.offset(2));
+    }
+
+    #[test]
+    fn is_readable_only_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = path("permissions");
+
+        let mut snapshot = ConfigSnapshot::new();
+        snapshot.set("first", 0, Some(b"secret"));
+        snapshot.save(&path, "applications");
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(0o600, mode & 0o777);
+    }
+
+    #[test]
+    fn ignores_a_snapshot_of_another_topic() {
+        let path = path("other_topic");
+
+        let mut snapshot = ConfigSnapshot::new();
+        snapshot.set("first", 0, Some(b"one"));
+        snapshot.save(&path, "applications");
+
+        let loaded = ConfigSnapshot::load(&path, "other.applications");
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_none());
+    }
+
+    #[test]
+    fn ignores_a_snapshot_of_another_version() {
+        let path = path("other_version");
+
+        {
+            let mut file = File::create(&path).unwrap();
+            let mut output = CodedOutputStream::new(&mut file);
+
+            output.write_uint32_no_tag(SNAPSHOT_VERSION + 1).unwrap();
+            output.write_string_no_tag("applications").unwrap();
+            output.write_uint32_no_tag(0).unwrap();
+            output.flush().unwrap();
+        }
+
+        let loaded = ConfigSnapshot::load(&path, "applications");
+        fs::remove_file(&path).unwrap();
+
+        assert!(loaded.is_none());
+    }
+
+    #[test]
+    fn ignores_a_missing_snapshot() {
+        assert!(ConfigSnapshot::load(&path("missing"), "applications").is_none());
+    }
+}
@@ -0,0 +1,91 @@
+use kafka::{Config, Routing, offset_tracker::TrackedOffset};
+use transport::{Delivery, Incoming, Record, Transport};
+
+use std::sync::Arc;
+
+/// Header holding the reason why the message was rejected.
+pub const REASON_HEADER: &str = "dead_letter_reason";
+
+/// Writes the messages we could not handle, as-is, to a separate topic for
+/// debugging and replaying.
+pub struct DeadLetterProducer {
+    topic: String,
+    transport: Arc<Transport>,
+}
+
+impl DeadLetterProducer {
+    /// A producer for the `dead_letter_topic`, `None` if not configured.
+    pub fn new(config: &Config, transport: Arc<Transport>) -> Option<DeadLetterProducer> {
+        config.dead_letter_topic.as_ref().map(|topic| {
+            DeadLetterProducer {
+                topic: topic.clone(),
+                transport,
+            }
+        })
+    }
+
+    /// Sends the original payload, key and headers of the message, adding the
+    /// reason and the source of the message as headers.
+    pub fn publish(&self, msg: &Incoming, reason: &str) -> Delivery {
+        let headers = Self::with_source(
+            msg.headers.clone(),
+            reason,
+            &msg.topic,
+            msg.partition,
+            msg.offset,
+        );
+
+        self.send(msg.payload.clone(), msg.key.clone(), headers)
+    }
+
+    /// Sends a decoded request we couldn't respond to, with the request
+    /// headers, the reason and the source of the message as headers.
+    pub fn publish_request(
+        &self,
+        source: &TrackedOffset,
+        routing: &Routing,
+        payload: &[u8],
+        reason: &str,
+    ) -> Delivery {
+        let headers = Self::with_source(
+            routing.headers.clone(),
+            reason,
+            source.topic(),
+            source.partition(),
+            source.offset(),
+        );
+
+        self.send(payload.to_vec(), routing.key.clone(), headers)
+    }
+
+    fn with_source(
+        mut headers: Vec<(String, Vec<u8>)>,
+        reason: &str,
+        topic: &str,
+        partition: i32,
+        offset: i64,
+    ) -> Vec<(String, Vec<u8>)> {
+        headers.push((REASON_HEADER.to_string(), reason.as_bytes().to_vec()));
+        headers.push(("source_topic".to_string(), topic.as_bytes().to_vec()));
+        headers.push(("source_partition".to_string(), partition.to_string().into_bytes()));
+        headers.push(("source_offset".to_string(), offset.to_string().into_bytes()));
+
+        headers
+    }
+
+    fn send(
+        &self,
+        payload: Vec<u8>,
+        key: Option<Vec<u8>>,
+        headers: Vec<(String, Vec<u8>)>,
+    ) -> Delivery {
+        let record = Record {
+            topic: self.topic.clone(),
+            key,
+            headers,
+            payload,
+        };
+
+        self.transport.send(record)
+    }
+}
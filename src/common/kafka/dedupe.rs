@@ -0,0 +1,275 @@
+use events::{
+    push_notification::PushNotification,
+    push_result::PushResult_ResponseAction as ResponseAction,
+};
+use kafka::DedupeConfig;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A notification seen before.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Seen {
+    /// Not seen, or seen too long ago.
+    New,
+    /// Seen and not responded yet.
+    InFlight,
+    /// Responded with the action.
+    Responded(ResponseAction),
+}
+
+struct Entry {
+    seen_at: Instant,
+    result: Option<ResponseAction>,
+}
+
+struct Window {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<String, Entry>,
+    order: VecDeque<(String, Instant)>,
+}
+
+/// Remembers the notifications by universe and correlation ID, so a
+/// notification delivered again by Kafka is not sent twice.
+pub struct DedupeWindow {
+    window: Mutex<Window>,
+}
+
+/// A new notification in the window, carried in its `Routing` to store the
+/// response.
+#[derive(Clone)]
+pub struct Deduped {
+    window: Arc<DedupeWindow>,
+    key: String,
+}
+
+impl DedupeWindow {
+    pub fn new(config: &DedupeConfig) -> DedupeWindow {
+        let window = Window {
+            capacity: config.window_size,
+            ttl: Duration::from_millis(config.ttl_ms),
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        };
+
+        DedupeWindow {
+            window: Mutex::new(window),
+        }
+    }
+
+    /// Checks if the notification was seen, marking a new one to be in
+    /// flight.
+    pub fn check(&self, event: &PushNotification) -> Seen {
+        let key = match Self::key(event) {
+            Some(key) => key,
+            None => return Seen::New,
+        };
+
+        let mut window = self.window.lock().unwrap();
+        let now = Instant::now();
+
+        window.evict(now);
+
+        let seen = match window.entries.get(&key) {
+            Some(entry) => match entry.result {
+                Some(action) => Seen::Responded(action),
+                None => Seen::InFlight,
+            },
+            None => Seen::New,
+        };
+
+        if seen == Seen::New {
+            window.insert(key, now);
+        }
+
+        seen
+    }
+
+    /// The universe and the correlation ID, `None` without a correlation ID.
+    fn key(event: &PushNotification) -> Option<String> {
+        let correlation_id = event.get_header().get_correlation_id();
+
+        if correlation_id.is_empty() {
+            None
+        } else {
+            Some(format!("{}|{}", event.get_universe(), correlation_id))
+        }
+    }
+
+    fn responded(&self, key: &str, action: ResponseAction) {
+        if let Some(entry) = self.window.lock().unwrap().entries.get_mut(key) {
+            entry.result = Some(action);
+        }
+    }
+
+    fn forget(&self, key: &str) {
+        self.window.lock().unwrap().entries.remove(key);
+    }
+}
+
+impl Deduped {
+    /// The handle for a notification found new with `check`, `None` without
+    /// a correlation ID.
+    pub fn new(window: Arc<DedupeWindow>, event: &PushNotification) -> Option<Deduped> {
+        DedupeWindow::key(event).map(|key| Deduped { window, key })
+    }
+
+    /// Stores the response for the duplicates. A retry is not the final
+    /// result, so the notification is forgotten and handled again when
+    /// delivered again.
+    pub fn responded(&self, action: ResponseAction) {
+        if action == ResponseAction::Retry {
+            self.forget();
+        } else {
+            self.window.responded(&self.key, action);
+        }
+    }
+
+    /// Forgets the notification, letting it through the next time.
+    pub fn forget(&self) {
+        self.window.forget(&self.key);
+    }
+}
+
+impl fmt::Debug for Deduped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deduped").field("key", &self.key).finish()
+    }
+}
+
+impl Window {
+    fn insert(&mut self, key: String, now: Instant) {
+        self.order.push_back((key.clone(), now));
+        self.entries.insert(key, Entry { seen_at: now, result: None });
+
+        self.evict(now);
+    }
+
+    /// Removes the oldest notifications over the capacity and the ones
+    /// seen longer than `ttl` ago.
+    fn evict(&mut self, now: Instant) {
+        loop {
+            let evict = match self.order.front() {
+                Some(&(_, seen_at)) => {
+                    self.order.len() > self.capacity || now.duration_since(seen_at) >= self.ttl
+                }
+                None => false,
+            };
+
+            if !evict {
+                break;
+            }
+
+            if let Some((oldest, seen_at)) = self.order.pop_front() {
+                let replaced = self.entries
+                    .get(&oldest)
+                    .map(|entry| entry.seen_at != seen_at)
+                    .unwrap_or(true);
+
+                if !replaced {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn window(window_size: usize, ttl_ms: u64) -> Arc<DedupeWindow> {
+        Arc::new(DedupeWindow::new(&DedupeConfig { window_size, ttl_ms }))
+    }
+
+    fn event(correlation_id: &str) -> PushNotification {
+        let mut event = PushNotification::new();
+        event.set_universe("universe".to_string());
+        event.mut_header().set_correlation_id(correlation_id.to_string());
+        event
+    }
+
+    #[test]
+    fn skips_duplicates_in_flight() {
+        let window = window(10, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+        assert_eq!(Seen::InFlight, window.check(&event("1")));
+        assert_eq!(Seen::New, window.check(&event("2")));
+    }
+
+    #[test]
+    fn replays_the_final_response() {
+        let window = window(10, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+
+        let deduped = Deduped::new(window.clone(), &event("1")).unwrap();
+        deduped.responded(ResponseAction::UnsubscribeEntity);
+
+        assert_eq!(
+            Seen::Responded(ResponseAction::UnsubscribeEntity),
+            window.check(&event("1"))
+        );
+    }
+
+    #[test]
+    fn forgets_retried_notifications() {
+        let window = window(10, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+
+        let deduped = Deduped::new(window.clone(), &event("1")).unwrap();
+        deduped.responded(ResponseAction::Retry);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+    }
+
+    #[test]
+    fn forgets_failed_notifications() {
+        let window = window(10, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+        Deduped::new(window.clone(), &event("1")).unwrap().forget();
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+    }
+
+    #[test]
+    fn evicts_the_oldest_over_the_capacity() {
+        let window = window(2, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+        assert_eq!(Seen::New, window.check(&event("2")));
+        assert_eq!(Seen::New, window.check(&event("3")));
+
+        assert_eq!(Seen::InFlight, window.check(&event("3")));
+        assert_eq!(Seen::New, window.check(&event("1")));
+    }
+
+    #[test]
+    fn evicts_expired_notifications() {
+        let window = window(10, 10);
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+        thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(Seen::New, window.check(&event("1")));
+        assert_eq!(1, window.window.lock().unwrap().entries.len());
+    }
+
+    #[test]
+    fn lets_notifications_without_a_correlation_id_through() {
+        let window = window(10, 60_000);
+
+        assert_eq!(Seen::New, window.check(&event("")));
+        assert_eq!(Seen::New, window.check(&event("")));
+        assert!(Deduped::new(window.clone(), &event("")).is_none());
+    }
+}
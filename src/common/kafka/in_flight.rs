@@ -0,0 +1,203 @@
+use futures::{Async, Future, Poll, task::{self, Task}};
+use metrics::{CONSUMER_SATURATED, TENANT_SATURATED};
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+struct State {
+    total: usize,
+    tenants: HashMap<String, usize>,
+    saturated_tenants: HashSet<String>,
+    waiting: Vec<Task>,
+}
+
+struct Inner {
+    max_total: Option<usize>,
+    max_per_tenant: Option<usize>,
+    state: Mutex<State>,
+}
+
+/// Limits the number of requests processed at the same time, globally and
+/// per tenant. The consumer stops reading from Kafka until a permit is
+/// available. A tenant over its limit doesn't block the consumer, the caller
+/// decides what to do with its request.
+#[derive(Clone)]
+pub struct InFlightLimiter {
+    inner: Arc<Inner>,
+}
+
+/// A slot for one request in flight, freed when dropped.
+pub struct Permit {
+    limiter: InFlightLimiter,
+    tenant: Option<String>,
+}
+
+/// The outcome of `InFlightLimiter::acquire`.
+pub enum Acquired {
+    Permit(Permit),
+    /// The tenant has too many requests in flight.
+    TenantSaturated,
+}
+
+/// Resolves when there is capacity for the request, waiting only for the
+/// global limit.
+pub struct Acquire {
+    limiter: InFlightLimiter,
+    tenant: Option<String>,
+    saturated: bool,
+}
+
+impl InFlightLimiter {
+    pub fn new(max_total: Option<usize>, max_per_tenant: Option<usize>) -> InFlightLimiter {
+        let state = State {
+            total: 0,
+            tenants: HashMap::new(),
+            saturated_tenants: HashSet::new(),
+            waiting: Vec::new(),
+        };
+
+        let inner = Inner {
+            max_total,
+            max_per_tenant,
+            state: Mutex::new(state),
+        };
+
+        InFlightLimiter { inner: Arc::new(inner) }
+    }
+
+    /// Waits for a free slot, failing right away if the tenant is at its
+    /// limit.
+    pub fn acquire(&self, tenant: Option<&str>) -> Acquire {
+        Acquire {
+            limiter: self.clone(),
+            tenant: tenant.map(|t| t.to_string()),
+            saturated: false,
+        }
+    }
+
+    /// True if the tenant is below its limit.
+    pub fn tenant_available(&self, tenant: &str) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        let tenant_count = state.tenants.get(tenant).cloned().unwrap_or(0);
+
+        self.inner.max_per_tenant
+            .map(|max_per_tenant| tenant_count < max_per_tenant)
+            .unwrap_or(true)
+    }
+
+    fn try_acquire(&self, tenant: Option<&str>) -> Poll<bool, ()> {
+        let mut state = self.inner.state.lock().unwrap();
+
+        if let Some(max_total) = self.inner.max_total {
+            if state.total >= max_total {
+                state.waiting.push(task::current());
+                return Ok(Async::NotReady);
+            }
+        }
+
+        if let Some(tenant) = tenant {
+            let tenant_count = state.tenants.get(tenant).cloned().unwrap_or(0);
+
+            if let Some(max_per_tenant) = self.inner.max_per_tenant {
+                if tenant_count >= max_per_tenant {
+                    if state.saturated_tenants.insert(tenant.to_string()) {
+                        warn!(
+                            "Too many requests in flight for the tenant, pausing its partitions";
+                            "universe" => tenant
+                        );
+
+                        TENANT_SATURATED.with_label_values(&[tenant]).set(1.0);
+                    }
+
+                    return Ok(Async::Ready(false));
+                }
+            }
+
+            if state.saturated_tenants.remove(tenant) {
+                info!("Resuming the tenant"; "universe" => tenant);
+                TENANT_SATURATED.with_label_values(&[tenant]).set(0.0);
+            }
+
+            state.tenants.insert(tenant.to_string(), tenant_count + 1);
+        }
+
+        state.total += 1;
+
+        Ok(Async::Ready(true))
+    }
+
+    fn release(&self, tenant: Option<&str>) {
+        let waiting = {
+            let mut state = self.inner.state.lock().unwrap();
+
+            state.total -= 1;
+
+            if let Some(tenant) = tenant {
+                let remove = match state.tenants.get_mut(tenant) {
+                    Some(count) => {
+                        *count -= 1;
+                        *count == 0
+                    }
+                    None => false,
+                };
+
+                if remove {
+                    state.tenants.remove(tenant);
+                }
+            }
+
+            state.waiting.drain(..).collect::<Vec<Task>>()
+        };
+
+        for task in waiting {
+            task.notify();
+        }
+    }
+}
+
+impl Future for Acquire {
+    type Item = Acquired;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Acquired, ()> {
+        let acquired = match self.limiter.try_acquire(self.tenant.as_ref().map(|t| t.as_str()))? {
+            Async::Ready(acquired) => acquired,
+            Async::NotReady => {
+                if !self.saturated {
+                    warn!("Too many requests in flight, pausing the consumer");
+
+                    self.saturated = true;
+                    CONSUMER_SATURATED.set(1.0);
+                }
+
+                return Ok(Async::NotReady);
+            }
+        };
+
+        if self.saturated {
+            info!("Resuming the consumer");
+
+            self.saturated = false;
+            CONSUMER_SATURATED.set(0.0);
+        }
+
+        if !acquired {
+            return Ok(Async::Ready(Acquired::TenantSaturated));
+        }
+
+        let permit = Permit {
+            limiter: self.limiter.clone(),
+            tenant: self.tenant.take(),
+        };
+
+        Ok(Async::Ready(Acquired::Permit(permit)))
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.release(self.tenant.as_ref().map(|t| t.as_str()));
+    }
+}
@@ -0,0 +1,110 @@
+use futures::{Async, Poll, Stream};
+use kafka::LaneMode;
+
+struct Lane<S> {
+    stream: S,
+    weight: u32,
+    done: bool,
+}
+
+/// Merges the streams of the priority lanes, the first lane having the
+/// highest priority. Ends when all the lanes end.
+pub struct Lanes<S> {
+    lanes: Vec<Lane<S>>,
+    mode: LaneMode,
+    current: usize,
+    served: u32,
+}
+
+impl<S: Stream> Lanes<S> {
+    /// Lanes from the streams and their weights, in the order of priority.
+    pub fn new(streams: Vec<(S, u32)>, mode: LaneMode) -> Lanes<S> {
+        let lanes = streams
+            .into_iter()
+            .map(|(stream, weight)| Lane {
+                stream,
+                weight: weight.max(1),
+                done: false,
+            })
+            .collect();
+
+        Lanes {
+            lanes,
+            mode,
+            current: 0,
+            served: 0,
+        }
+    }
+
+    /// Polls the lane, `None` if it has nothing to give right now.
+    fn poll_lane(&mut self, index: usize) -> Result<Option<S::Item>, S::Error> {
+        let lane = &mut self.lanes[index];
+
+        if lane.done {
+            return Ok(None);
+        }
+
+        match lane.stream.poll()? {
+            Async::Ready(Some(item)) => Ok(Some(item)),
+            Async::Ready(None) => {
+                lane.done = true;
+                Ok(None)
+            }
+            Async::NotReady => Ok(None),
+        }
+    }
+
+    /// The first lane with a message.
+    fn poll_strict(&mut self) -> Result<Option<S::Item>, S::Error> {
+        for index in 0..self.lanes.len() {
+            if let Some(item) = self.poll_lane(index)? {
+                return Ok(Some(item));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The current lane until it has given its weight of messages or has
+    /// nothing to give, then the next one. The lane we started from is
+    /// polled again at the end with a full weight.
+    fn poll_weighted(&mut self) -> Result<Option<S::Item>, S::Error> {
+        for _ in 0..=self.lanes.len() {
+            if self.served < self.lanes[self.current].weight {
+                let index = self.current;
+
+                if let Some(item) = self.poll_lane(index)? {
+                    self.served += 1;
+                    return Ok(Some(item));
+                }
+            }
+
+            self.current = (self.current + 1) % self.lanes.len();
+            self.served = 0;
+        }
+
+        Ok(None)
+    }
+}
+
+impl<S: Stream> Stream for Lanes<S> {
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+        if self.lanes.iter().all(|lane| lane.done) {
+            return Ok(Async::Ready(None));
+        }
+
+        let item = match self.mode {
+            LaneMode::Strict => self.poll_strict()?,
+            LaneMode::Weighted => self.poll_weighted()?,
+        };
+
+        match item {
+            Some(item) => Ok(Async::Ready(Some(item))),
+            None if self.lanes.iter().all(|lane| lane.done) => Ok(Async::Ready(None)),
+            None => Ok(Async::NotReady),
+        }
+    }
+}
@@ -1,11 +1,98 @@
+mod config_snapshot;
+mod dead_letter_producer;
+mod dedupe;
+mod in_flight;
+mod lanes;
+mod offset_tracker;
+mod parking;
+mod rate_limiter;
 mod request_consumer;
 mod response_producer;
+mod routing;
 
 pub use self::request_consumer::{EventHandler, RequestConsumer};
-pub use self::response_producer::ResponseProducer;
-pub use rdkafka::producer::DeliveryFuture;
+pub use self::response_producer::{ResponseProducer, delivered};
+pub use self::routing::Routing;
 
-#[derive(Deserialize, Debug)]
+use config::{Key, Kind};
+use rdkafka::config::ClientConfig;
+use std::{collections::HashMap, fmt, path::Path};
+
+/// Shown in the logs instead of the passwords.
+const REDACTED: &str = "****";
+
+/// The keys of the `[kafka]` section, matching `Config`.
+pub const KEYS: &[Key] = &[
+    Key { name: "input_topic", kind: Kind::String, required: true },
+    Key { name: "config_topic", kind: Kind::String, required: true },
+    Key { name: "config_snapshot", kind: Kind::String, required: false },
+    Key { name: "priority_lanes", kind: Kind::Table(PRIORITY_LANES_KEYS), required: false },
+    Key { name: "output_topic", kind: Kind::String, required: true },
+    Key { name: "reply_topics", kind: Kind::Strings, required: false },
+    Key { name: "dead_letter_topic", kind: Kind::String, required: false },
+    Key { name: "retry_topics", kind: Kind::Tables(RETRY_TOPIC_KEYS), required: false },
+    Key { name: "forward_headers", kind: Kind::Strings, required: false },
+    Key { name: "schedule_topics", kind: Kind::Tables(SCHEDULE_TOPIC_KEYS), required: false },
+    Key { name: "group_id", kind: Kind::String, required: true },
+    Key { name: "brokers", kind: Kind::String, required: true },
+    Key { name: "security_protocol", kind: Kind::String, required: false },
+    Key { name: "sasl_mechanisms", kind: Kind::String, required: false },
+    Key { name: "sasl_username", kind: Kind::String, required: false },
+    Key { name: "sasl_password", kind: Kind::String, required: false },
+    Key { name: "ssl_ca_location", kind: Kind::String, required: false },
+    Key { name: "ssl_certificate_location", kind: Kind::String, required: false },
+    Key { name: "ssl_key_location", kind: Kind::String, required: false },
+    Key { name: "ssl_key_password", kind: Kind::String, required: false },
+    Key { name: "consumer", kind: Kind::StringMap, required: false },
+    Key { name: "producer", kind: Kind::StringMap, required: false },
+    Key { name: "offset_commit", kind: Kind::Choice(&["auto", "after_delivery"]), required: false },
+    Key { name: "shutdown_grace_period_ms", kind: Kind::Integer, required: false },
+    Key { name: "max_in_flight", kind: Kind::Integer, required: false },
+    Key { name: "max_in_flight_per_tenant", kind: Kind::Integer, required: false },
+    Key { name: "dedupe", kind: Kind::Table(DEDUPE_KEYS), required: false },
+    Key { name: "rate_limits", kind: Kind::Table(RATE_LIMITS_KEYS), required: false },
+];
+
+const RETRY_TOPIC_KEYS: &[Key] = &[
+    Key { name: "topic", kind: Kind::String, required: true },
+    Key { name: "delay_ms", kind: Kind::Integer, required: true },
+];
+
+const SCHEDULE_TOPIC_KEYS: &[Key] = &[
+    Key { name: "topic", kind: Kind::String, required: true },
+    Key { name: "delay_ms", kind: Kind::Integer, required: true },
+];
+
+const PRIORITY_LANES_KEYS: &[Key] = &[
+    Key { name: "mode", kind: Kind::Choice(&["strict", "weighted"]), required: false },
+    Key { name: "input_weight", kind: Kind::Integer, required: false },
+    Key { name: "topics", kind: Kind::Tables(PRIORITY_TOPIC_KEYS), required: false },
+];
+
+const PRIORITY_TOPIC_KEYS: &[Key] = &[
+    Key { name: "topic", kind: Kind::String, required: true },
+    Key { name: "weight", kind: Kind::Integer, required: false },
+];
+
+const DEDUPE_KEYS: &[Key] = &[
+    Key { name: "window_size", kind: Kind::Integer, required: true },
+    Key { name: "ttl_ms", kind: Kind::Integer, required: true },
+];
+
+const RATE_LIMITS_KEYS: &[Key] = &[
+    Key { name: "default", kind: Kind::Table(RATE_LIMIT_KEYS), required: false },
+    Key { name: "universes", kind: Kind::TableMap(RATE_LIMIT_KEYS), required: false },
+    Key { name: "exceeded", kind: Kind::Choice(&["delay", "reject"]), required: false },
+    Key { name: "max_delay_ms", kind: Kind::Integer, required: false },
+];
+
+const RATE_LIMIT_KEYS: &[Key] = &[
+    Key { name: "per_second", kind: Kind::Float, required: true },
+    Key { name: "burst", kind: Kind::Float, required: false },
+];
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// Kafka topic for incoming `PushNotification` events, triggering a push
     /// notification to be sent.
@@ -13,10 +100,521 @@ pub struct Config {
     /// Kafka topic for incoming `Application` events, holding the tenant
     /// configuration.
     pub config_topic: String,
+    /// File for the tenant configuration and the config topic offsets. On
+    /// startup the configuration is loaded from the file and the config topic
+    /// read from the stored offsets. If not set, the whole config topic is
+    /// replayed.
+    pub config_snapshot: Option<String>,
+    /// Input topics consumed ahead of the `input_topic`, e.g. for
+    /// transactional notifications. Default: none.
+    #[serde(default)]
+    pub priority_lanes: PriorityLanes,
     /// Kafka topic for push notification responses.
     pub output_topic: String,
+    /// Topics the requests are allowed to name for their responses, with the
+    /// `reply_to` field of the rpc header or the `reply_to` Kafka header.
+    /// Other responses go to the `output_topic`. Default: none.
+    #[serde(default)]
+    pub reply_topics: Vec<String>,
+    /// Kafka topic for the input messages we couldn't parse or handle. If not
+    /// set, the invalid messages are dropped.
+    pub dead_letter_topic: Option<String>,
+    /// Topics for retrying failed push notifications, in the order of the
+    /// attempts. When all of them are tried, the response is sent with
+    /// `ResponseAction::Retry`.
+    #[serde(default)]
+    pub retry_topics: Vec<RetryTopic>,
+    /// Headers of the request copied to the response, e.g. trace IDs.
+    /// Default: none.
+    #[serde(default)]
+    pub forward_headers: Vec<String>,
+    /// Topics for the requests scheduled with the `send_at` header in the
+    /// future, every one holding the requests for a fixed delay. A request
+    /// goes to the topic with the longest delay not past `send_at`, until
+    /// due. Without schedule topics the requests are sent right away.
+    /// Default: none.
+    #[serde(default)]
+    pub schedule_topics: Vec<ScheduleTopic>,
     /// Kafka consumer group ID.
     pub group_id: String,
     /// A comma-separated list of Kafka brokers to connect.
     pub brokers: String,
+    /// Protocol to talk to the brokers: `plaintext`, `ssl`, `sasl_plaintext`
+    /// or `sasl_ssl`. Default: `plaintext`.
+    pub security_protocol: Option<String>,
+    /// SASL mechanism for authentication, e.g. `PLAIN` or `SCRAM-SHA-256`.
+    pub sasl_mechanisms: Option<String>,
+    /// SASL username.
+    pub sasl_username: Option<String>,
+    /// SASL password.
+    pub sasl_password: Option<String>,
+    /// Path to the CA certificate for verifying the brokers.
+    pub ssl_ca_location: Option<String>,
+    /// Path to the client certificate.
+    pub ssl_certificate_location: Option<String>,
+    /// Path to the client private key.
+    pub ssl_key_location: Option<String>,
+    /// Password for the client private key.
+    pub ssl_key_password: Option<String>,
+    /// Raw librdkafka properties for the consumers. They override the
+    /// brokers and the security settings, but not the properties the
+    /// consumers set themselves, such as `group.id` or `enable.auto.commit`.
+    #[serde(default)]
+    pub consumer: HashMap<String, String>,
+    /// Raw librdkafka properties for the producers. They override the
+    /// brokers and the security settings, but not the properties the
+    /// producers set themselves, such as `produce.offset.report`.
+    #[serde(default)]
+    pub producer: HashMap<String, String>,
+    /// Decides when the offsets of the input topic are committed. Default:
+    /// `auto`.
+    #[serde(default)]
+    pub offset_commit: OffsetCommit,
+    /// How long to wait for the requests in flight when shutting down, before
+    /// committing the offsets and exiting. Default: 10000.
+    pub shutdown_grace_period_ms: Option<u64>,
+    /// Maximum number of requests processed at the same time. The consumer
+    /// pauses when reached. Default: unlimited.
+    pub max_in_flight: Option<usize>,
+    /// Maximum number of requests processed at the same time for one tenant.
+    /// The partition of a request over the limit is paused until the tenant
+    /// has room. Default: unlimited.
+    pub max_in_flight_per_tenant: Option<usize>,
+    /// Skips the notifications with a universe and correlation ID seen
+    /// recently, responding with the previous result. Default: disabled.
+    pub dedupe: Option<DedupeConfig>,
+    /// Notifications per second for the tenants. Default: unlimited.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+}
+
+impl Config {
+    /// Base configuration for the consumers with the brokers, security
+    /// settings and the `[kafka.consumer]` properties.
+    pub fn consumer_config(&self) -> ClientConfig {
+        self.client_config(&self.consumer)
+    }
+
+    /// Base configuration for the producers with the brokers, security
+    /// settings and the `[kafka.producer]` properties.
+    pub fn producer_config(&self) -> ClientConfig {
+        self.client_config(&self.producer)
+    }
+
+    /// Checks the values the types can't enforce, returning all the problems
+    /// found.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let required = vec![
+            ("input_topic", &self.input_topic),
+            ("config_topic", &self.config_topic),
+            ("output_topic", &self.output_topic),
+            ("group_id", &self.group_id),
+            ("brokers", &self.brokers),
+        ];
+
+        for (key, value) in required {
+            if value.trim().is_empty() {
+                errors.push(format!("kafka.{} must not be empty", key));
+            }
+        }
+
+        let limits = vec![
+            ("max_in_flight", self.max_in_flight),
+            ("max_in_flight_per_tenant", self.max_in_flight_per_tenant),
+        ];
+
+        for (key, limit) in limits {
+            if limit == Some(0) {
+                errors.push(format!("kafka.{} must be greater than zero", key));
+            }
+        }
+
+        if let Some(ref protocol) = self.security_protocol {
+            match protocol.to_lowercase().as_str() {
+                "plaintext" | "ssl" | "sasl_plaintext" | "sasl_ssl" => (),
+                _ => errors.push(format!(
+                    "kafka.security_protocol must be one of plaintext, ssl, sasl_plaintext or sasl_ssl, got `{}`",
+                    protocol
+                )),
+            }
+        }
+
+        if self.sasl_username.is_some() != self.sasl_password.is_some() {
+            errors.push(String::from(
+                "kafka.sasl_username and kafka.sasl_password must be set together",
+            ));
+        }
+
+        let files = vec![
+            ("ssl_ca_location", &self.ssl_ca_location),
+            ("ssl_certificate_location", &self.ssl_certificate_location),
+            ("ssl_key_location", &self.ssl_key_location),
+        ];
+
+        for (key, file) in files {
+            if let Some(ref file) = *file {
+                if !Path::new(file).exists() {
+                    errors.push(format!("kafka.{}: {} does not exist", key, file));
+                }
+            }
+        }
+
+        if let Some(ref snapshot) = self.config_snapshot {
+            let directory = Path::new(snapshot)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+
+            if !directory.is_dir() {
+                errors.push(format!(
+                    "kafka.config_snapshot: directory {} does not exist",
+                    directory.display()
+                ));
+            }
+        }
+
+        if self.offset_commit == OffsetCommit::AfterDelivery && self.dead_letter_topic.is_none() {
+            errors.push(String::from(
+                "kafka.dead_letter_topic is required with offset_commit = \"after_delivery\"",
+            ));
+        }
+
+        if self.dead_letter_topic.as_ref() == Some(&self.input_topic) {
+            errors.push(String::from(
+                "kafka.dead_letter_topic must differ from the input topic",
+            ));
+        }
+
+        let rate_limits = self.rate_limits.default
+            .iter()
+            .map(|limit| (String::from("kafka.rate_limits.default"), limit))
+            .chain(self.rate_limits.universes.iter().map(|(universe, limit)| {
+                (format!("kafka.rate_limits.universes.{}", universe), limit)
+            }));
+
+        for (key, limit) in rate_limits {
+            if limit.per_second <= 0.0 {
+                errors.push(format!("{}.per_second must be greater than zero", key));
+            }
+
+            if limit.burst.map(|burst| burst < 1.0).unwrap_or(false) {
+                errors.push(format!("{}.burst must be at least one", key));
+            }
+        }
+
+        if let Some(ref dedupe) = self.dedupe {
+            if dedupe.window_size == 0 {
+                errors.push(String::from("kafka.dedupe.window_size must be greater than zero"));
+            }
+
+            if dedupe.ttl_ms == 0 {
+                errors.push(String::from("kafka.dedupe.ttl_ms must be greater than zero"));
+            }
+        }
+
+        for topic in self.reply_topics.iter() {
+            if topic == &self.input_topic || topic == &self.config_topic {
+                errors.push(format!(
+                    "kafka.reply_topics must not contain the input or config topic, got `{}`",
+                    topic
+                ));
+            }
+        }
+
+        for (i, lane) in self.priority_lanes.topics.iter().enumerate() {
+            if lane.topic.trim().is_empty() {
+                errors.push(format!("kafka.priority_lanes.topics[{}].topic must not be empty", i));
+            }
+
+            if lane.topic == self.input_topic || lane.topic == self.config_topic {
+                errors.push(format!(
+                    "kafka.priority_lanes.topics[{}].topic must differ from the input and config topics",
+                    i
+                ));
+            }
+
+            let internal = self.retry_topics.iter().any(|retry| retry.topic == lane.topic)
+                || self.schedule_topics.iter().any(|schedule| schedule.topic == lane.topic)
+                || self.dead_letter_topic.as_ref() == Some(&lane.topic);
+
+            if internal {
+                errors.push(format!(
+                    "kafka.priority_lanes.topics[{}].topic must differ from the retry, schedule and dead letter topics",
+                    i
+                ));
+            }
+
+            if self.priority_lanes.topics[..i].iter().any(|other| other.topic == lane.topic) {
+                errors.push(format!(
+                    "kafka.priority_lanes.topics[{}].topic `{}` is listed twice",
+                    i, lane.topic
+                ));
+            }
+
+            if lane.weight == Some(0) {
+                errors.push(format!(
+                    "kafka.priority_lanes.topics[{}].weight must be greater than zero",
+                    i
+                ));
+            }
+        }
+
+        if self.priority_lanes.input_weight == Some(0) {
+            errors.push(String::from(
+                "kafka.priority_lanes.input_weight must be greater than zero",
+            ));
+        }
+
+        for (i, retry) in self.retry_topics.iter().enumerate() {
+            if retry.topic.trim().is_empty() {
+                errors.push(format!("kafka.retry_topics[{}].topic must not be empty", i));
+            }
+
+            if retry.topic == self.input_topic {
+                errors.push(format!(
+                    "kafka.retry_topics[{}].topic must differ from the input topic",
+                    i
+                ));
+            }
+        }
+
+        for (i, schedule) in self.schedule_topics.iter().enumerate() {
+            if schedule.topic.trim().is_empty() {
+                errors.push(format!("kafka.schedule_topics[{}].topic must not be empty", i));
+            }
+
+            if schedule.topic == self.input_topic {
+                errors.push(format!(
+                    "kafka.schedule_topics[{}].topic must differ from the input topic",
+                    i
+                ));
+            }
+
+            if schedule.delay_ms == 0 {
+                errors.push(format!(
+                    "kafka.schedule_topics[{}].delay_ms must be greater than zero",
+                    i
+                ));
+            }
+        }
+
+        errors
+    }
+
+    fn client_config(&self, properties: &HashMap<String, String>) -> ClientConfig {
+        let mut client_config = ClientConfig::new();
+
+        client_config.set("bootstrap.servers", &self.brokers);
+
+        let security = vec![
+            ("security.protocol", &self.security_protocol),
+            ("sasl.mechanisms", &self.sasl_mechanisms),
+            ("sasl.username", &self.sasl_username),
+            ("sasl.password", &self.sasl_password),
+            ("ssl.ca.location", &self.ssl_ca_location),
+            ("ssl.certificate.location", &self.ssl_certificate_location),
+            ("ssl.key.location", &self.ssl_key_location),
+            ("ssl.key.password", &self.ssl_key_password),
+        ];
+
+        for (key, value) in security {
+            if let Some(ref value) = *value {
+                client_config.set(key, value);
+            }
+        }
+
+        for (key, value) in properties.iter() {
+            client_config.set(key, value);
+        }
+
+        client_config
+    }
+
+    /// The raw properties with the values of the password and secret keys
+    /// hidden.
+    fn redacted(properties: &HashMap<String, String>) -> HashMap<&str, &str> {
+        properties
+            .iter()
+            .map(|(key, value)| {
+                let secret = key.contains("password") || key.contains("secret");
+                (key.as_str(), if secret { REDACTED } else { value.as_str() })
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let redact = |password: &Option<String>| password.as_ref().map(|_| REDACTED);
+
+        f.debug_struct("Config")
+            .field("input_topic", &self.input_topic)
+            .field("config_topic", &self.config_topic)
+            .field("config_snapshot", &self.config_snapshot)
+            .field("priority_lanes", &self.priority_lanes)
+            .field("output_topic", &self.output_topic)
+            .field("reply_topics", &self.reply_topics)
+            .field("dead_letter_topic", &self.dead_letter_topic)
+            .field("retry_topics", &self.retry_topics)
+            .field("forward_headers", &self.forward_headers)
+            .field("schedule_topics", &self.schedule_topics)
+            .field("group_id", &self.group_id)
+            .field("brokers", &self.brokers)
+            .field("security_protocol", &self.security_protocol)
+            .field("sasl_mechanisms", &self.sasl_mechanisms)
+            .field("sasl_username", &self.sasl_username)
+            .field("sasl_password", &redact(&self.sasl_password))
+            .field("ssl_ca_location", &self.ssl_ca_location)
+            .field("ssl_certificate_location", &self.ssl_certificate_location)
+            .field("ssl_key_location", &self.ssl_key_location)
+            .field("ssl_key_password", &redact(&self.ssl_key_password))
+            .field("consumer", &Self::redacted(&self.consumer))
+            .field("producer", &Self::redacted(&self.producer))
+            .field("offset_commit", &self.offset_commit)
+            .field("shutdown_grace_period_ms", &self.shutdown_grace_period_ms)
+            .field("max_in_flight", &self.max_in_flight)
+            .field("max_in_flight_per_tenant", &self.max_in_flight_per_tenant)
+            .field("dedupe", &self.dedupe)
+            .field("rate_limits", &self.rate_limits)
+            .finish()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetryTopic {
+    /// Kafka topic for the delayed notifications.
+    pub topic: String,
+    /// How long to wait before sending the notification again.
+    pub delay_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleTopic {
+    /// Kafka topic for the scheduled requests.
+    pub topic: String,
+    /// How long the requests wait in the topic before going on.
+    pub delay_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityLanes {
+    /// How the lanes share the consumer. Default: `strict`.
+    #[serde(default)]
+    pub mode: LaneMode,
+    /// Share of the `input_topic` in `weighted` mode. Default: 1.
+    pub input_weight: Option<u32>,
+    /// Topics consumed ahead of the `input_topic`, the highest priority
+    /// first.
+    #[serde(default)]
+    pub topics: Vec<PriorityTopic>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PriorityTopic {
+    /// Kafka topic for the notifications of the lane.
+    pub topic: String,
+    /// Share of the lane in `weighted` mode. Default: 1.
+    pub weight: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LaneMode {
+    /// A lane is read only when the lanes before it have nothing to read.
+    Strict,
+    /// Every lane with messages gets its weight of messages in turn.
+    Weighted,
+}
+
+impl Default for LaneMode {
+    fn default() -> LaneMode {
+        LaneMode::Strict
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DedupeConfig {
+    /// Maximum number of notifications remembered.
+    pub window_size: usize,
+    /// How long a notification is remembered.
+    pub ttl_ms: u64,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimits {
+    /// The limit for every tenant without a limit of its own.
+    pub default: Option<RateLimit>,
+    /// Limits per universe, overridden by the limit in the `Application`
+    /// event.
+    #[serde(default)]
+    pub universes: HashMap<String, RateLimit>,
+    /// What to do with the notifications over the limit. Default: `delay`.
+    #[serde(default)]
+    pub exceeded: RateLimitExceeded,
+    /// The longest a notification is delayed before it's rejected. Default:
+    /// 60000.
+    pub max_delay_ms: Option<u64>,
+}
+
+impl RateLimits {
+    pub fn max_delay_ms(&self) -> u64 {
+        self.max_delay_ms.unwrap_or(60_000)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Notifications per second on average.
+    pub per_second: f64,
+    /// Notifications sent at once after a quiet period. Default: `per_second`.
+    pub burst: Option<f64>,
+}
+
+impl RateLimit {
+    pub fn burst(&self) -> f64 {
+        self.burst.unwrap_or(self.per_second).max(1.0)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitExceeded {
+    /// Wait until the tenant has tokens left, rejecting if it would take
+    /// longer than `max_delay_ms`.
+    Delay,
+    /// Respond right away with `ResponseAction::RateLimited`.
+    Reject,
+}
+
+impl Default for RateLimitExceeded {
+    fn default() -> RateLimitExceeded {
+        RateLimitExceeded::Delay
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetCommit {
+    /// Offsets are committed periodically up to the requests taken into
+    /// handling, in-flight messages might get lost in a crash.
+    Auto,
+    /// Offsets are committed only after the response, or the request to the
+    /// dead letter topic, is written, giving at-least-once delivery.
+    /// Requires a `dead_letter_topic`.
+    AfterDelivery,
+}
+
+impl Default for OffsetCommit {
+    fn default() -> OffsetCommit {
+        OffsetCommit::Auto
+    }
 }
@@ -0,0 +1,238 @@
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Mutex,
+};
+
+/// A handle to an offset given out by `OffsetTracker::track`. Completing it
+/// after a rebalance is a no-op.
+pub struct TrackedOffset {
+    generation: u64,
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+impl TrackedOffset {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn partition(&self) -> i32 {
+        self.partition
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+struct PartitionOffsets {
+    in_flight: BTreeSet<i64>,
+    next: i64,
+    committed: Option<i64>,
+}
+
+impl PartitionOffsets {
+    /// The offset from where the consumer should continue after a restart:
+    /// the oldest message still in flight or the next one after the latest
+    /// seen message.
+    fn commit_point(&self) -> i64 {
+        self.in_flight
+            .iter()
+            .next()
+            .cloned()
+            .unwrap_or(self.next)
+    }
+}
+
+struct Partitions {
+    generation: u64,
+    offsets: HashMap<(String, i32), PartitionOffsets>,
+}
+
+/// Keeps track of the messages being processed per partition, so we only
+/// commit the offsets of the messages we've already responded to.
+pub struct OffsetTracker {
+    partitions: Mutex<Partitions>,
+}
+
+impl OffsetTracker {
+    pub fn new() -> OffsetTracker {
+        let partitions = Partitions {
+            generation: 0,
+            offsets: HashMap::new(),
+        };
+
+        OffsetTracker {
+            partitions: Mutex::new(partitions),
+        }
+    }
+
+    /// Marks the message to be in flight.
+    pub fn track(&self, topic: &str, partition: i32, offset: i64) -> TrackedOffset {
+        let mut partitions = self.partitions.lock().unwrap();
+        let generation = partitions.generation;
+
+        let entry = partitions.offsets
+            .entry((topic.to_string(), partition))
+            .or_insert_with(|| PartitionOffsets {
+                in_flight: BTreeSet::new(),
+                next: offset,
+                committed: None,
+            });
+
+        entry.in_flight.insert(offset);
+
+        if offset >= entry.next {
+            entry.next = offset + 1;
+        }
+
+        TrackedOffset {
+            generation,
+            topic: topic.to_string(),
+            partition,
+            offset,
+        }
+    }
+
+    /// Marks the message to be handled and the response delivered.
+    pub fn complete(&self, tracked: &TrackedOffset) {
+        let mut partitions = self.partitions.lock().unwrap();
+
+        if partitions.generation != tracked.generation {
+            return;
+        }
+
+        let key = (tracked.topic.clone(), tracked.partition);
+
+        if let Some(entry) = partitions.offsets.get_mut(&key) {
+            entry.in_flight.remove(&tracked.offset);
+        }
+    }
+
+    /// Offsets of the topics that moved forward since the previous call,
+    /// `None` if nothing is to be committed.
+    pub fn committable(&self, topics: &[String]) -> Option<TopicPartitionList> {
+        let mut partitions = self.partitions.lock().unwrap();
+        let mut list = TopicPartitionList::new();
+        let mut changed = false;
+
+        for (&(ref topic, partition), entry) in partitions.offsets.iter_mut() {
+            if !topics.contains(topic) {
+                continue;
+            }
+
+            let commit_point = entry.commit_point();
+
+            if entry.committed != Some(commit_point) {
+                list.add_partition_offset(topic, partition, Offset::Offset(commit_point));
+                entry.committed = Some(commit_point);
+                changed = true;
+            }
+        }
+
+        if changed { Some(list) } else { None }
+    }
+
+    /// Forgets all partitions. Called when the group rebalances, the messages
+    /// still in flight will not affect the new assignment.
+    pub fn reset(&self) {
+        let mut partitions = self.partitions.lock().unwrap();
+
+        partitions.generation += 1;
+        partitions.offsets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(tracker: &OffsetTracker, topic: &str) -> Vec<(i32, i64)> {
+        let topics = vec![topic.to_string()];
+
+        let mut offsets: Vec<(i32, i64)> = tracker
+            .committable(&topics)
+            .map(|list| {
+                list.elements()
+                    .iter()
+                    .map(|elem| match elem.offset() {
+                        Offset::Offset(offset) => (elem.partition(), offset),
+                        offset => panic!("unexpected offset {:?}", offset),
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        offsets.sort();
+        offsets
+    }
+
+    #[test]
+    fn commits_up_to_the_oldest_message_in_flight() {
+        let tracker = OffsetTracker::new();
+
+        let first = tracker.track("input", 0, 10);
+        let second = tracker.track("input", 0, 11);
+        let third = tracker.track("input", 0, 12);
+
+        assert_eq!(vec![(0, 10)], offsets(&tracker, "input"));
+
+        tracker.complete(&second);
+        tracker.complete(&third);
+        assert!(tracker.committable(&["input".to_string()]).is_none());
+
+        tracker.complete(&first);
+        assert_eq!(vec![(0, 13)], offsets(&tracker, "input"));
+    }
+
+    #[test]
+    fn skips_gaps_in_the_offsets() {
+        let tracker = OffsetTracker::new();
+
+        let first = tracker.track("input", 0, 10);
+        let second = tracker.track("input", 0, 15);
+
+        tracker.complete(&second);
+        assert_eq!(vec![(0, 10)], offsets(&tracker, "input"));
+
+        tracker.complete(&first);
+        assert_eq!(vec![(0, 16)], offsets(&tracker, "input"));
+    }
+
+    #[test]
+    fn commits_only_the_requested_topics_and_changed_partitions() {
+        let tracker = OffsetTracker::new();
+
+        let input = tracker.track("input", 0, 1);
+        tracker.track("input", 1, 5);
+        let retry = tracker.track("retry", 0, 7);
+
+        tracker.complete(&input);
+        tracker.complete(&retry);
+
+        assert_eq!(vec![(0, 2), (1, 5)], offsets(&tracker, "input"));
+        assert_eq!(vec![(0, 8)], offsets(&tracker, "retry"));
+
+        tracker.track("input", 0, 2);
+        assert!(tracker.committable(&["input".to_string()]).is_none());
+    }
+
+    #[test]
+    fn ignores_offsets_tracked_before_a_reset() {
+        let tracker = OffsetTracker::new();
+
+        let old = tracker.track("input", 0, 10);
+        tracker.reset();
+
+        let new = tracker.track("input", 0, 20);
+        tracker.complete(&old);
+
+        assert_eq!(vec![(0, 20)], offsets(&tracker, "input"));
+
+        tracker.complete(&new);
+        assert_eq!(vec![(0, 21)], offsets(&tracker, "input"));
+    }
+}
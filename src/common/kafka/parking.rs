@@ -0,0 +1,174 @@
+use kafka::in_flight::InFlightLimiter;
+use transport::Input;
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// When a parked partition can continue.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Wake {
+    /// When the time comes.
+    At(Instant),
+    /// When the tenant has room for more requests in flight.
+    Tenant(String),
+}
+
+struct Parked {
+    offset: i64,
+    wake: Wake,
+    paused: bool,
+}
+
+/// Partitions stopped at a message that can't be handled yet. The partition
+/// is paused in the input, and the messages already fetched after the
+/// parked one are skipped. When it's time to continue, the input seeks
+/// back to the parked message and resumes the partition, so nothing is held
+/// in memory and the offsets are never committed past the parked message.
+pub struct Parking {
+    parked: Mutex<HashMap<(String, i32), Parked>>,
+}
+
+impl Parking {
+    pub fn new() -> Parking {
+        Parking {
+            parked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// True if the messages from the partition should be skipped, to be
+    /// read again when the partition continues.
+    pub fn is_parked(&self, topic: &str, partition: i32) -> bool {
+        self.parked
+            .lock()
+            .unwrap()
+            .contains_key(&(topic.to_string(), partition))
+    }
+
+    /// Stops the partition at the message until `wake`. The partition is
+    /// paused on the next `update`.
+    pub fn park(&self, topic: &str, partition: i32, offset: i64, wake: Wake) {
+        debug!(
+            "Parking the partition";
+            "topic" => topic,
+            "partition" => partition,
+            "offset" => offset,
+            "wake" => format!("{:?}", wake)
+        );
+
+        let parked = Parked {
+            offset,
+            wake,
+            paused: false,
+        };
+
+        self.parked
+            .lock()
+            .unwrap()
+            .insert((topic.to_string(), partition), parked);
+    }
+
+    /// Pauses the newly parked partitions of the topics in the input, and
+    /// continues the ones ready from the parked message.
+    pub fn update(
+        &self,
+        input: &Input,
+        topics: &[String],
+        limiter: &InFlightLimiter,
+    ) {
+        let now = Instant::now();
+        let mut parked = self.parked.lock().unwrap();
+        let mut continuing = Vec::new();
+
+        for (&(ref topic, partition), entry) in parked.iter_mut() {
+            if !topics.contains(topic) {
+                continue;
+            }
+
+            let ready = match entry.wake {
+                Wake::At(due) => due <= now,
+                Wake::Tenant(ref tenant) => limiter.tenant_available(tenant),
+            };
+
+            if ready {
+                continuing.push((topic.clone(), partition));
+                continue;
+            }
+
+            if !entry.paused {
+                entry.paused = Self::pause(input, topic, partition);
+            }
+        }
+
+        for key in continuing {
+            let (ref topic, partition) = key;
+            let offset = parked[&key].offset;
+
+            // The messages after the parked one were skipped, so the
+            // partition can't continue from anywhere else. Stays parked and
+            // paused, the seek is tried again on the next update.
+            if let Err(e) = input.seek(topic, partition, offset) {
+                warn!(
+                    "Couldn't seek back to the parked message, retrying";
+                    "topic" => topic,
+                    "partition" => partition,
+                    "offset" => offset,
+                    "error" => e
+                );
+
+                if let Some(entry) = parked.get_mut(&key) {
+                    if !entry.paused {
+                        entry.paused = Self::pause(input, topic, partition);
+                    }
+                }
+
+                continue;
+            }
+
+            if let Some(entry) = parked.remove(&key) {
+                if entry.paused {
+                    if let Err(e) = input.resume(topic, partition) {
+                        warn!(
+                            "Couldn't resume the partition";
+                            "topic" => topic,
+                            "partition" => partition,
+                            "error" => e
+                        );
+                    }
+                }
+
+                debug!(
+                    "Partition continues from the parked message";
+                    "topic" => topic,
+                    "partition" => partition,
+                    "offset" => offset
+                );
+            }
+        }
+    }
+
+    /// Pauses the partition in the input, true if paused.
+    fn pause(input: &Input, topic: &str, partition: i32) -> bool {
+        match input.pause(topic, partition) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(
+                    "Couldn't pause the partition";
+                    "topic" => topic,
+                    "partition" => partition,
+                    "error" => e
+                );
+
+                false
+            }
+        }
+    }
+
+    /// Forgets the parked partitions. Called when the group rebalances, the
+    /// partitions continue from the committed offsets.
+    pub fn reset(&self) {
+        self.parked.lock().unwrap().clear();
+    }
+}
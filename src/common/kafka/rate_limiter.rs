@@ -0,0 +1,160 @@
+use events::application::Application;
+use kafka::{RateLimit, RateLimitExceeded, RateLimits};
+use metrics::THROTTLED_COUNTER;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// What to do with a notification.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    /// Send right away.
+    Now,
+    /// Send after waiting for the given time.
+    After(Duration),
+    /// Respond with `ResponseAction::RateLimited` without sending.
+    Rejected,
+}
+
+/// Tokens of a tenant. In `delay` mode the tokens can go negative, the debt
+/// telling how long the next notification has to wait.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst());
+        self.updated = now;
+    }
+}
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    overrides: HashMap<String, RateLimit>,
+}
+
+struct Inner {
+    limits: RateLimits,
+    state: Mutex<State>,
+}
+
+/// Token-bucket rate limits per universe. The limit of a tenant comes from
+/// the `Application` event, the `[kafka.rate_limits.universes]` table or the
+/// default limit, in that order.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> RateLimiter {
+        let state = State {
+            buckets: HashMap::new(),
+            overrides: HashMap::new(),
+        };
+
+        let inner = Inner {
+            limits,
+            state: Mutex::new(state),
+        };
+
+        RateLimiter { inner: Arc::new(inner) }
+    }
+
+    /// Sets or removes the limit from the tenant configuration.
+    pub fn configure(&self, universe: &str, application: Option<&Application>) {
+        let limit = application
+            .filter(|application| application.has_rate_limit())
+            .map(|application| {
+                let rate_limit = application.get_rate_limit();
+                let burst = rate_limit.get_burst();
+
+                RateLimit {
+                    per_second: f64::from(rate_limit.get_per_second()),
+                    burst: if burst > 0 { Some(f64::from(burst)) } else { None },
+                }
+            });
+
+        let mut state = self.inner.state.lock().unwrap();
+
+        match limit {
+            Some(limit) => {
+                state.overrides.insert(universe.to_string(), limit);
+            }
+            None => {
+                state.overrides.remove(universe);
+            }
+        }
+
+        state.buckets.remove(universe);
+    }
+
+    /// Takes a token for a notification of the universe.
+    pub fn admit(&self, universe: &str) -> Admission {
+        let mut state = self.inner.state.lock().unwrap();
+
+        let limit = match state.overrides.get(universe) {
+            Some(limit) => limit.clone(),
+            None => match self.inner.limits.universes.get(universe) {
+                Some(limit) => limit.clone(),
+                None => match self.inner.limits.default {
+                    Some(ref limit) => limit.clone(),
+                    None => return Admission::Now,
+                },
+            },
+        };
+
+        if limit.per_second <= 0.0 {
+            return Admission::Now;
+        }
+
+        let now = Instant::now();
+
+        let bucket = state
+            .buckets
+            .entry(universe.to_string())
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst(),
+                updated: now,
+            });
+
+        bucket.refill(&limit, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Admission::Now;
+        }
+
+        let admission = match self.inner.limits.exceeded {
+            RateLimitExceeded::Reject => Admission::Rejected,
+            RateLimitExceeded::Delay => {
+                let wait_ms = (1.0 - bucket.tokens) / limit.per_second * 1000.0;
+
+                if wait_ms > self.inner.limits.max_delay_ms() as f64 {
+                    Admission::Rejected
+                } else {
+                    bucket.tokens -= 1.0;
+                    Admission::After(Duration::from_millis(wait_ms.ceil() as u64))
+                }
+            }
+        };
+
+        let outcome = match admission {
+            Admission::Rejected => "rejected",
+            _ => "delayed",
+        };
+
+        debug!("Rate limit exceeded"; "universe" => universe, "outcome" => outcome);
+        THROTTLED_COUNTER.with_label_values(&[universe, outcome]).inc();
+
+        admission
+    }
+}
@@ -2,20 +2,71 @@ use rdkafka::{
     Message,
     message::BorrowedMessage,
     config::ClientConfig,
-    consumer::{CommitMode, Consumer, stream_consumer::StreamConsumer},
+    consumer::{
+        CommitMode,
+        Consumer,
+        ConsumerContext,
+        stream_consumer::StreamConsumer,
+    },
+    error::KafkaResult,
     topic_partition_list::{Offset, TopicPartitionList},
 };
-use kafka::Config;
+use kafka::{
+    Config,
+    LaneMode,
+    OffsetCommit,
+    Routing,
+    ScheduleTopic,
+    config_snapshot::ConfigSnapshot,
+    dead_letter_producer::DeadLetterProducer,
+    dedupe::{DedupeWindow, Deduped, Seen},
+    in_flight::{Acquired, InFlightLimiter, Permit},
+    lanes::Lanes,
+    offset_tracker::{OffsetTracker, TrackedOffset},
+    parking::{Parking, Wake},
+    rate_limiter::{Admission, RateLimiter},
+    response_producer::ResponseProducer,
+    response_producer::{RETRY_AT_HEADER, RETRY_ATTEMPT_HEADER},
+    routing::{REPLY_TO_HEADER, SCHEDULE_AT_HEADER, SEND_AT_HEADER},
+};
 use events::{
     application::Application,
     push_notification::PushNotification,
     http_request::HttpRequest,
+    push_result::PushResult_ResponseAction as ResponseAction,
     rpc_decoder::RequestWrapper,
 };
-use futures::{Future, Stream, sync::oneshot};
-use protobuf::parse_from_bytes;
-use tokio::{self, runtime::current_thread::Runtime};
+use futures::{
+    Future,
+    IntoFuture,
+    Stream,
+    future::{self, Either},
+    sync::oneshot,
+};
+use protobuf::{Message as ProtobufMessage, parse_from_bytes};
+use tokio::{
+    self,
+    runtime::current_thread::Runtime,
+    timer::{Delay, Interval},
+};
 use regex::Regex;
+use chrono::Utc;
+use metrics::{
+    CONFIG_PARTITION_APPLICATIONS,
+    DUPLICATES_COUNTER,
+    EXPIRED_COUNTER,
+    REPLY_TO_REJECTED_COUNTER,
+};
+use status::STATUS;
+use transport::{Delivery, Incoming, Input, Record, Revoked, Transport};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    str,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 lazy_static! {
     static ref APP_KEY_RE: Regex =
@@ -24,23 +75,50 @@ lazy_static! {
         ).unwrap();
 }
 
+/// How often the processed offsets are committed.
+const COMMIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the parked partitions are checked to continue.
+const PARKING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the config topic is checked for new partitions.
+const METADATA_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait for the brokers when fetching the config topic metadata
+/// and watermarks.
+const METADATA_TIMEOUT_MS: i32 = 10_000;
+
+/// How long to wait before fetching the config topic metadata again when the
+/// brokers can't be reached on startup.
+const METADATA_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often the requests in flight are checked when shutting down.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long the producers get to send the queued messages when shutting
+/// down.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time to wait for the requests in flight when shutting down.
+const DEFAULT_GRACE_PERIOD_MS: u64 = 10_000;
+
 pub trait EventHandler {
     /// True if the consumer should accept the incoming event.
     fn accepts(&self, event: &PushNotification) -> bool;
 
-    /// Try to send a push notification. If key parameter is set, the response
-    /// will be sent with the same routing key.
+    /// Try to send a push notification. The response should be sent with the
+    /// given routing.
     fn handle_notification(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send>;
 
-    /// Try to send a http request. If key parameter is set, the response
-    /// will be sent with the same routing key.
+    /// Try to send a http request. The response should be sent with the
+    /// given routing.
     fn handle_http(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: HttpRequest,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send>;
 
@@ -52,220 +130,1284 @@ pub trait EventHandler {
     );
 }
 
+/// A decoded request from the input topic, with the routing information.
+enum Request {
+    Push(Routing, PushNotification),
+    Http(Routing, HttpRequest),
+}
+
+impl Request {
+    /// The tenant for per-tenant in-flight limits.
+    fn tenant(&self) -> Option<&str> {
+        match *self {
+            Request::Push(_, ref event) => Some(event.get_universe()),
+            Request::Http(_, _) => None,
+        }
+    }
+
+    fn routing(&self) -> &Routing {
+        match *self {
+            Request::Push(ref routing, _) => routing,
+            Request::Http(ref routing, _) => routing,
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        match *self {
+            Request::Push(_, ref event) => event.write_to_bytes().unwrap(),
+            Request::Http(_, ref event) => event.write_to_bytes().unwrap(),
+        }
+    }
+}
+
+/// Topics read with one input, and their share of the messages in
+/// `LaneMode::Weighted`. The offsets and parked partitions are tracked per
+/// lane, a rebalance of one input not touching the others.
+struct InputLane {
+    topics: Vec<String>,
+    weight: u32,
+    tracker: Arc<OffsetTracker>,
+    parking: Arc<Parking>,
+}
+
+impl InputLane {
+    fn new(topics: Vec<String>, weight: u32) -> InputLane {
+        InputLane {
+            topics,
+            weight,
+            tracker: Arc::new(OffsetTracker::new()),
+            parking: Arc::new(Parking::new()),
+        }
+    }
+}
+
+/// The parts of the consumer a request needs once dispatched.
+struct Dispatcher<H> {
+    handler: Arc<H>,
+    responses: ResponseProducer,
+    dead_letters: Option<Arc<DeadLetterProducer>>,
+    dedupe: Option<Arc<DedupeWindow>>,
+    tracker: Arc<OffsetTracker>,
+}
+
 pub struct RequestConsumer<H: EventHandler + Send + Sync + 'static> {
     config_topic: String,
-    input_topic: String,
+    config_snapshot: Option<String>,
+    lanes: Vec<InputLane>,
+    lane_mode: LaneMode,
     group_id: String,
-    brokers: String,
-    handler: H,
+    consumer_config: ClientConfig,
+    offset_commit: OffsetCommit,
+    limiter: InFlightLimiter,
+    rate_limiter: RateLimiter,
+    responses: ResponseProducer,
+    dead_letters: Option<Arc<DeadLetterProducer>>,
+    dedupe: Option<Arc<DedupeWindow>>,
+    reply_topics: Vec<String>,
+    schedule_topics: Vec<ScheduleTopic>,
+    grace_period: Duration,
+    transport: Arc<Transport>,
+    handler: Arc<H>,
 }
 
 impl<H: EventHandler + Send + Sync + 'static> RequestConsumer<H> {
-    /// A kafka consumer to consume push notification events. `EventHandler`
-    /// should contain the business logic.
-    pub fn new(handler: H, config: &Config) -> RequestConsumer<H> {
+    /// A consumer for push notification events, read from the inputs of
+    /// `transport`. `EventHandler` should contain the business logic,
+    /// responding through `transport`.
+    pub fn new(handler: H, transport: Arc<Transport>, config: &Config) -> RequestConsumer<H> {
         RequestConsumer {
             config_topic: config.config_topic.clone(),
-            input_topic: config.input_topic.clone(),
+            config_snapshot: config.config_snapshot.clone(),
+            lanes: Self::input_lanes(config),
+            lane_mode: config.priority_lanes.mode,
             group_id: config.group_id.clone(),
-            brokers: config.brokers.clone(),
-            handler,
+            consumer_config: config.consumer_config(),
+            offset_commit: config.offset_commit,
+            limiter: InFlightLimiter::new(
+                config.max_in_flight,
+                config.max_in_flight_per_tenant,
+            ),
+            rate_limiter: RateLimiter::new(config.rate_limits.clone()),
+            responses: ResponseProducer::new(config, transport.clone()),
+            dead_letters: DeadLetterProducer::new(config, transport.clone()).map(Arc::new),
+            dedupe: config.dedupe.as_ref().map(|dedupe| Arc::new(DedupeWindow::new(dedupe))),
+            reply_topics: config.reply_topics.clone(),
+            schedule_topics: config.schedule_topics.clone(),
+            grace_period: Duration::from_millis(
+                config.shutdown_grace_period_ms.unwrap_or(DEFAULT_GRACE_PERIOD_MS)
+            ),
+            transport,
+            handler: Arc::new(handler),
         }
     }
 
-    /// Consuming the configuration topic for tenant connection setup. A message through `control` stops the consumer.
-    pub fn handle_configs(&self, control: oneshot::Receiver<()>) -> Result<(), ()> {
-        let consumer: StreamConsumer = ClientConfig::new()
+    /// The priority lanes in order, ending with the `input_topic` together
+    /// with the retry and schedule topics.
+    fn input_lanes(config: &Config) -> Vec<InputLane> {
+        let mut lanes: Vec<InputLane> = config.priority_lanes.topics
+            .iter()
+            .map(|lane| InputLane::new(vec![lane.topic.clone()], lane.weight.unwrap_or(1)))
+            .collect();
+
+        let mut topics = vec![config.input_topic.clone()];
+        topics.extend(config.retry_topics.iter().map(|retry| retry.topic.clone()));
+        topics.extend(config.schedule_topics.iter().map(|schedule| schedule.topic.clone()));
+
+        lanes.push(InputLane::new(topics, config.priority_lanes.input_weight.unwrap_or(1)));
+
+        lanes
+    }
+
+    /// The lane reading the topic.
+    fn lane(&self, topic: &str) -> &InputLane {
+        self.lanes
+            .iter()
+            .find(|lane| lane.topics.iter().any(|t| t == topic))
+            .unwrap_or_else(|| &self.lanes[self.lanes.len() - 1])
+    }
+
+    /// Consuming the configuration topic for tenant connection setup. With a
+    /// snapshot, the stored configuration is loaded first and the topic read
+    /// from the stored offsets. A message through `control` stops the
+    /// consumer.
+    pub fn handle_configs(&self, mut control: oneshot::Receiver<()>) -> Result<(), ()> {
+        let consumer: StreamConsumer = self.consumer_config
+            .clone()
             .set("group.id", &self.group_id)
-            .set("bootstrap.servers", &self.brokers)
             .set("enable.auto.commit", "false")
             .set("auto.offset.reset", "earliest")
             .set("enable.partition.eof", "false")
             .create()
             .expect("Consumer creation failed");
 
+        let watermarks = match self.config_watermarks(&consumer, &mut control) {
+            Some(watermarks) => watermarks,
+            None => return Ok(()),
+        };
+
+        let partition_count = watermarks.len() as i32;
+
+        if partition_count == 0 {
+            warn!("Config topic has no partitions"; "config_topic" => &self.config_topic);
+        }
+
+        let mut snapshot = self.config_snapshot
+            .as_ref()
+            .and_then(|path| ConfigSnapshot::load(path, &self.config_topic));
+
         let mut partitions = TopicPartitionList::new();
 
-        for partition in 0..12 {
-            partitions.add_partition_offset(
-                &self.config_topic,
-                partition,
-                Offset::Beginning
-            );
+        // Where every partition continues from, to keep the place when
+        // partitions are added to the assignment.
+        let mut positions = HashMap::new();
+
+        // The requests are not handled before we reach these offsets in the
+        // config topic.
+        let mut replaying = HashMap::new();
+
+        for (partition, &(low, high)) in watermarks.iter().enumerate() {
+            let partition = partition as i32;
+
+            let stored = snapshot
+                .as_ref()
+                .and_then(|snapshot| snapshot.offset(partition));
+
+            // The topic was recreated or cleaned past the stored offset, the
+            // snapshot might miss changes.
+            let start = match stored {
+                Some(offset) if offset >= low && offset <= high => Some(offset),
+                Some(offset) => {
+                    warn!(
+                        "Config snapshot out of range, replaying the partition";
+                        "partition" => partition,
+                        "offset" => offset,
+                        "low" => low,
+                        "high" => high
+                    );
+
+                    if let Some(ref mut snapshot) = snapshot {
+                        snapshot.reset_partition(partition);
+                    }
+
+                    None
+                }
+                None => None,
+            };
+
+            let position = start.map(Offset::Offset).unwrap_or(Offset::Beginning);
+
+            partitions.add_partition_offset(&self.config_topic, partition, position);
+            positions.insert(partition, position);
+
+            if high > start.unwrap_or(low) {
+                replaying.insert(partition, high);
+            }
         }
 
         consumer.assign(&partitions).expect("Can't subscribe to specified topics");
 
-        info!("Starting config processing");
+        let loaded: RefCell<HashMap<i32, HashSet<String>>> = RefCell::new(HashMap::new());
+
+        if let Some(ref snapshot) = snapshot {
+            for (application_id, partition, payload) in snapshot.applications() {
+                loaded
+                    .borrow_mut()
+                    .entry(partition)
+                    .or_insert_with(HashSet::new)
+                    .insert(application_id.clone());
+
+                self.handle_config(&application_id, Some(&payload));
+            }
+        }
+
+        // Keeps the snapshot up to date only if we have a file for it.
+        let snapshot = RefCell::new(match self.config_snapshot {
+            Some(_) => Some(snapshot.unwrap_or_else(ConfigSnapshot::new)),
+            None => None,
+        });
+
+        let save_snapshot = || {
+            if let Some(ref path) = self.config_snapshot {
+                if let Some(ref mut snapshot) = *snapshot.borrow_mut() {
+                    snapshot.save(path, &self.config_topic);
+                }
+            }
+        };
+
+        info!(
+            "Starting config processing";
+            "config_topic" => &self.config_topic,
+            "partitions" => partition_count
+        );
 
-        self.handler(consumer, control, &|msg: BorrowedMessage| {
-            let convert_key = msg.key().and_then(|key| {
-                String::from_utf8(key.to_vec()).ok()
+        if replaying.is_empty() {
+            info!("Nothing to replay in the config topic, ready to handle requests");
+            STATUS.set_config_replayed();
+        }
+
+        let replaying = RefCell::new(replaying);
+        let positions = RefCell::new(positions);
+
+        let partition_count = Cell::new(partition_count);
+        let reported: RefCell<HashMap<i32, usize>> = RefCell::new(HashMap::new());
+
+        let refresh = || {
+            self.report_loaded(&loaded.borrow(), &mut reported.borrow_mut());
+            self.assign_new_partitions(&consumer, &partition_count, &positions);
+            save_snapshot();
+        };
+
+        let finish = || {
+            save_snapshot();
+
+            if let Err(e) = consumer.commit_consumer_state(CommitMode::Sync) {
+                warn!("Error committing the config consumer state"; "error" => format!("{:?}", e));
+            }
+        };
+
+        let messages = consumer
+            .start()
+            .filter_map(|result| match result {
+                Ok(msg) => Some(msg),
+                Err(e) => {
+                    warn!("Error while receiving from Kafka: {:?}", e);
+                    None
+                }
             });
 
-            match convert_key {
-                Some(ref key) if APP_KEY_RE.is_match(key) => {
-                    let type_parsing = msg.payload().and_then(|payload| {
-                        parse_from_bytes::<RequestWrapper>(&payload).ok()
-                    });
-
-                    let application_id: &str = key
-                        .split('|')
-                        .collect::<Vec<&str>>()[1];
-
-                    match type_parsing {
-                        Some(ref decoder) => {
-                            match decoder.get_header().get_field_type() {
-                                "application.Application" => {
-                                    debug!(
-                                        "Got application configuration";
-                                        "universe" => application_id,
-                                        "key" => key
-                                    );
-
-                                    self.handle_config(application_id, Some(&msg))
+        self.handler(
+            vec![(messages, 1)],
+            control,
+            None,
+            METADATA_INTERVAL,
+            refresh,
+            finish,
+            |msg: BorrowedMessage| {
+                let convert_key = msg.key().and_then(|key| {
+                    String::from_utf8(key.to_vec()).ok()
+                });
+
+                match convert_key {
+                    Some(ref key) if APP_KEY_RE.is_match(key) => {
+                        let type_parsing = msg.payload().and_then(|payload| {
+                            parse_from_bytes::<RequestWrapper>(&payload).ok()
+                        });
+
+                        let application_id: &str = key
+                            .split('|')
+                            .collect::<Vec<&str>>()[1];
+
+                        match type_parsing {
+                            Some(ref decoder) => {
+                                match decoder.get_header().get_field_type() {
+                                    "application.Application" => {
+                                        debug!(
+                                            "Got application configuration";
+                                            "universe" => application_id,
+                                            "key" => key
+                                        );
+
+                                        loaded
+                                            .borrow_mut()
+                                            .entry(msg.partition())
+                                            .or_insert_with(HashSet::new)
+                                            .insert(application_id.to_string());
+
+                                        if let Some(ref mut snapshot) = *snapshot.borrow_mut() {
+                                            snapshot.set(application_id, msg.partition(), msg.payload());
+                                        }
+
+                                        self.handle_config(application_id, msg.payload())
+                                    }
+                                    t =>
+                                        debug!("Invalid type: {}", t),
                                 }
-                                t =>
-                                    debug!("Invalid type: {}", t),
                             }
-                        }
-                        None => {
-                            debug!(
-                                "Got null configuration";
-                                "universe" => application_id,
-                                "key" => key
-                            );
-
-                            self.handle_config(application_id, None);
+                            None => {
+                                debug!(
+                                    "Got null configuration";
+                                    "universe" => application_id,
+                                    "key" => key
+                                );
+
+                                if let Some(ids) = loaded.borrow_mut().get_mut(&msg.partition()) {
+                                    ids.remove(application_id);
+                                }
+
+                                if let Some(ref mut snapshot) = *snapshot.borrow_mut() {
+                                    snapshot.set(application_id, msg.partition(), None);
+                                }
+
+                                self.handle_config(application_id, None);
+                            }
                         }
                     }
+                    _ => debug!("Not an application configuration here")
                 }
-                _ => debug!("Not an application configuration here")
-            }
 
-            Ok(())
-        })
+                if let Some(ref mut snapshot) = *snapshot.borrow_mut() {
+                    snapshot.processed(msg.partition(), msg.offset());
+                }
+
+                positions
+                    .borrow_mut()
+                    .insert(msg.partition(), Offset::Offset(msg.offset() + 1));
+
+                let replayed = {
+                    let mut replaying = replaying.borrow_mut();
+
+                    let partition_done = replaying
+                        .get(&msg.partition())
+                        .map(|high| msg.offset() + 1 >= *high)
+                        .unwrap_or(false);
+
+                    if partition_done {
+                        replaying.remove(&msg.partition());
+                        replaying.is_empty()
+                    } else {
+                        false
+                    }
+                };
+
+                if replayed {
+                    info!("Config topic replayed, ready to handle requests");
+                    self.report_loaded(&loaded.borrow(), &mut reported.borrow_mut());
+                    save_snapshot();
+                    STATUS.set_config_replayed();
+                }
+
+                Ok(())
+            },
+        )
     }
 
-    /// Consume until event is sent through `control`.
+    /// Consume until event is sent through `control`. Every lane is read
+    /// with an input of its own, the lanes before it taking precedence. The
+    /// transports without topics have one input, read as the last lane.
     pub fn handle_requests(&self, control: oneshot::Receiver<()>) -> Result<(), ()> {
-        let consumer: StreamConsumer = ClientConfig::new()
-            .set("group.id", &self.group_id)
-            .set("bootstrap.servers", &self.brokers)
-            .set("enable.auto.commit", "true")
-            .set("auto.offset.reset", "latest")
-            .set("enable.partition.eof", "false")
-            .create()
-            .expect("Consumer creation failed");
+        let mut inputs: Vec<(&InputLane, Box<Input>)> = self.lanes
+            .iter()
+            .rev()
+            .filter_map(|lane| {
+                let tracker = lane.tracker.clone();
+                let parking = lane.parking.clone();
+
+                let revoked: Revoked = Box::new(move || {
+                    debug!("Partitions revoked, resetting offset tracking");
+                    tracker.reset();
+                    parking.reset();
+                });
 
-        consumer.subscribe(&[&self.input_topic]).expect("Can't subscribe to specified topics");
+                self.transport
+                    .input(&lane.topics, revoked)
+                    .map(|input| (lane, input))
+            })
+            .collect();
+
+        inputs.reverse();
+
+        let has_configs = inputs.iter().any(|&(_, ref input)| input.has_configs());
+
+        info!("Waiting for the config topic to be replayed");
+
+        let subscribed = Cell::new(false);
+
+        // Subscribing only after all the applications are loaded, otherwise
+        // the notifications would fail due to missing configuration.
+        let subscribe = || {
+            if subscribed.get() || !STATUS.config_replayed() {
+                return;
+            }
+
+            for &(_, ref input) in inputs.iter() {
+                input.start().expect("Can't subscribe to specified topics");
+            }
 
-        info!("Starting events processing");
+            subscribed.set(true);
 
-        self.handler(consumer, control, &|msg: BorrowedMessage| {
-            debug!(
-                "Got message";
-                "topic" => msg.topic(),
-                "key" => msg.key().and_then(|key| String::from_utf8(key.to_vec()).ok())
+            let topics: Vec<&Vec<String>> = inputs.iter().map(|&(lane, _)| &lane.topics).collect();
+
+            info!(
+                "Starting events processing";
+                "offset_commit" => format!("{:?}", self.offset_commit),
+                "lane_mode" => format!("{:?}", self.lane_mode),
+                "lanes" => format!("{:?}", topics)
             );
+        };
 
-            let type_parsing = msg.payload()
-                .and_then(|payload| parse_from_bytes::<RequestWrapper>(&payload).ok());
+        let committed = Cell::new(Instant::now());
 
-            match type_parsing {
-                Some(ref decoder) => {
-                    match decoder.get_header().get_field_type() {
-                        "notification.PushNotification" =>
-                            self.handle_push(&msg),
-                        "http.HttpRequest" =>
-                            self.handle_http(&msg),
-                        t =>
-                            debug!("Invalid type: {}", t),
+        let tick = || {
+            subscribe();
 
-                    }
+            for &(lane, ref input) in inputs.iter() {
+                lane.parking.update(&**input, &lane.topics, &self.limiter);
+            }
+
+            if committed.get().elapsed() >= COMMIT_INTERVAL {
+                for &(lane, ref input) in inputs.iter() {
+                    Self::commit_offsets(lane, &**input, false);
                 }
-                None => {
-                    error!("Invalid RPC request");
+
+                committed.set(Instant::now());
+            }
+        };
+
+        let finish = || {
+            // Responses and dead letters still in the producer queue must be
+            // written before their offsets are committed.
+            self.transport.flush(FLUSH_TIMEOUT);
+
+            for &(lane, ref input) in inputs.iter() {
+                Self::commit_offsets(lane, &**input, true);
+            }
+        };
+
+        let streams = inputs
+            .iter()
+            .map(|&(lane, ref input)| (input.messages(), lane.weight))
+            .collect();
+
+        self.handler(
+            streams,
+            control,
+            Some(self.grace_period),
+            PARKING_INTERVAL,
+            tick,
+            finish,
+            |msg: Incoming| {
+                debug!(
+                    "Got message";
+                    "topic" => &msg.topic,
+                    "key" => msg.key.as_ref().and_then(|key| str::from_utf8(key).ok())
+                );
+
+                let lane = self.lane(&msg.topic);
+
+                // Fetched after a parked message, read again when the partition
+                // continues.
+                if lane.parking.is_parked(&msg.topic, msg.partition) {
+                    return Either::B(future::ok(()));
+                }
+
+                let topic = msg.topic.clone();
+                let partition = msg.partition;
+                let offset = msg.offset;
+
+                // The inputs without a config topic have the tenant
+                // configuration in between the requests.
+                if has_configs && self.configure_inline(&msg) {
+                    let tracked = lane.tracker.track(&topic, partition, offset);
+                    lane.tracker.complete(&tracked);
+
+                    return Either::B(future::ok(()));
                 }
+
+                let type_parsing = Self::decode::<RequestWrapper>(&msg);
+
+                let request = match type_parsing {
+                    Ok(ref decoder) => {
+                        match decoder.get_header().get_field_type() {
+                            "notification.PushNotification" =>
+                                self.parse_push(&msg),
+                            "http.HttpRequest" =>
+                                self.parse_http(&msg),
+                            t => {
+                                debug!("Invalid type: {}", t);
+                                Err(format!("invalid_type: {}", t))
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        error!("Invalid RPC request"; "error" => e.as_str());
+                        Err(format!("invalid_rpc_request: {}", e))
+                    }
+                };
+
+                match (request, self.dead_letters.as_ref()) {
+                    (Err(reason), Some(dead_letters)) => {
+                        let tracker = lane.tracker.clone();
+                        let tracked = tracker.track(&topic, partition, offset);
+
+                        let dead_letter = dead_letters
+                            .publish(&msg, &reason)
+                            .then(move |result| {
+                                if result.is_err() {
+                                    error!("Dead letter not written, skipping the message");
+                                }
+
+                                tracker.complete(&tracked);
+
+                                Ok(())
+                            });
+
+                        tokio::spawn(dead_letter);
+
+                        Either::B(future::ok(()))
+                    }
+                    (Ok(Some(request)), _) => {
+                        // A scheduled request waits in its schedule topic for
+                        // the delay of the topic, the partition continuing from
+                        // it at `schedule_at`. The delay is the same for every
+                        // request in the topic, so the ones behind it are never
+                        // due before.
+                        if let Some(schedule_at) = Self::due_at(&msg, SCHEDULE_AT_HEADER) {
+                            lane.parking.park(&topic, partition, offset, Wake::At(schedule_at));
+                            return Either::B(future::ok(()));
+                        }
+
+                        // A request scheduled in the future moves from one
+                        // schedule topic to the next until `send_at`.
+                        if let Some(wait_ms) = Self::wait_ms(&msg, SEND_AT_HEADER) {
+                            match self.schedule_topic(wait_ms) {
+                                Some(schedule_topic) => {
+                                    let tracked = lane.tracker.track(&topic, partition, offset);
+                                    self.reschedule(tracked, request, schedule_topic);
+
+                                    return Either::B(future::ok(()));
+                                }
+                                None => warn!(
+                                    "No schedule topics configured, sending the scheduled request now";
+                                    "wait_ms" => wait_ms
+                                ),
+                            }
+                        }
+
+                        // A retry waits in its topic until due, the partition
+                        // continuing from it at `retry_at`.
+                        if let Some(retry_at) = Self::due_at(&msg, RETRY_AT_HEADER) {
+                            lane.parking.park(&topic, partition, offset, Wake::At(retry_at));
+                            return Either::B(future::ok(()));
+                        }
+
+                        // Not reading the next message from Kafka until we have
+                        // room for this one. A tenant over its limit stops only
+                        // the partition, to be read again when the tenant has
+                        // room.
+                        let spawning = self.limiter
+                            .acquire(request.tenant())
+                            .map(move |acquired| match acquired {
+                                Acquired::Permit(permit) => {
+                                    let tracked = lane.tracker.track(&topic, partition, offset);
+                                    self.start(tracked, request, permit);
+                                }
+                                Acquired::TenantSaturated => {
+                                    let tenant = request.tenant().unwrap_or_default().to_string();
+                                    lane.parking.park(&topic, partition, offset, Wake::Tenant(tenant));
+                                }
+                            });
+
+                        Either::A(spawning)
+                    }
+                    _ => {
+                        let tracked = lane.tracker.track(&topic, partition, offset);
+                        lane.tracker.complete(&tracked);
+
+                        Either::B(future::ok(()))
+                    }
+                }
+            },
+        )
+    }
+
+    /// Starts handling a request holding a permit, taking a token from the
+    /// rate limiter right before dispatching it. Rate limited requests are
+    /// responded without sending, throttled ones wait for their turn. The
+    /// request counts as in flight from here, also while waiting, so the
+    /// shutdown drains it with the others.
+    fn start(&self, tracked: TrackedOffset, request: Request, permit: Permit) {
+        STATUS.request_started();
+
+        let admission = match request {
+            Request::Push(_, ref event) => self.rate_limiter.admit(event.get_universe()),
+            Request::Http(_, _) => Admission::Now,
+        };
+
+        let throttled = match admission {
+            Admission::Now => None,
+            Admission::After(wait) => Some(Instant::now() + wait),
+            Admission::Rejected => {
+                drop(permit);
+                self.reject(tracked, request);
+
+                return;
             }
+        };
 
-            Ok(())
-        })
+        let dispatcher = Dispatcher {
+            handler: self.handler.clone(),
+            responses: self.responses.clone(),
+            dead_letters: self.dead_letters.clone(),
+            dedupe: self.dedupe.clone(),
+            tracker: self.lane(tracked.topic()).tracker.clone(),
+        };
+
+        let offset_commit = self.offset_commit;
+
+        let dispatching = move || {
+            // With automatic commits the offset goes forward as soon as the
+            // request is handled, without waiting for the response.
+            if offset_commit == OffsetCommit::Auto {
+                dispatcher.tracker.complete(&tracked);
+            }
+
+            Self::dispatch(dispatcher, tracked, request, permit)
+        };
+
+        match throttled {
+            Some(due) => {
+                let waiting = Delay::new(due)
+                    .map_err(|e| warn!("Error in the throttle timer: {:?}", e))
+                    .then(move |_| dispatching());
+
+                tokio::spawn(waiting);
+            }
+            None => {
+                tokio::spawn(dispatching());
+            }
+        }
+    }
+
+    /// The schedule topic for a request due in `wait_ms`: the one with the
+    /// longest delay not past it, or the shortest delay if all of them are,
+    /// sending the request at most that late. `None` without schedule
+    /// topics.
+    fn schedule_topic(&self, wait_ms: u64) -> Option<&ScheduleTopic> {
+        self.schedule_topics
+            .iter()
+            .filter(|schedule| schedule.delay_ms <= wait_ms)
+            .max_by_key(|schedule| schedule.delay_ms)
+            .or_else(|| self.schedule_topics.iter().min_by_key(|schedule| schedule.delay_ms))
+    }
+
+    /// Writes a request scheduled in the future to the schedule topic, to be
+    /// read again after the delay of the topic. The offset is completed when
+    /// the request is written.
+    fn reschedule(&self, tracked: TrackedOffset, request: Request, schedule_topic: &ScheduleTopic) {
+        let (routing, payload) = match request {
+            Request::Push(routing, event) => (routing, event.write_to_bytes().unwrap()),
+            Request::Http(routing, event) => (routing, event.write_to_bytes().unwrap()),
+        };
+
+        let schedule_at = Utc::now().timestamp_millis() + schedule_topic.delay_ms as i64;
+        let mut headers = routing.headers;
+
+        if routing.attempt > 0 {
+            headers.push((
+                RETRY_ATTEMPT_HEADER.to_string(),
+                routing.attempt.to_string().into_bytes(),
+            ));
+        }
+
+        headers.push((SCHEDULE_AT_HEADER.to_string(), schedule_at.to_string().into_bytes()));
+
+        let record = Record {
+            topic: schedule_topic.topic.clone(),
+            key: routing.key,
+            headers,
+            payload,
+        };
+
+        let tracker = self.lane(tracked.topic()).tracker.clone();
+
+        let rescheduling = self.transport
+            .send(record)
+            .then(move |result| {
+                match result {
+                    Ok(()) => tracker.complete(&tracked),
+                    Err(()) => error!(
+                        "Scheduled request not written, keeping the offset uncommitted"
+                    ),
+                }
+
+                Ok(())
+            });
+
+        tokio::spawn(rescheduling);
+    }
+
+    /// Responds to a notification over the rate limit without sending it,
+    /// completing the offset when the response is written or has failed.
+    fn reject(&self, tracked: TrackedOffset, request: Request) {
+        if let Request::Push(routing, event) = request {
+            let tracker = self.lane(tracked.topic()).tracker.clone();
+
+            let responding = self.responses
+                .respond(routing, event, ResponseAction::RateLimited)
+                .then(move |result| {
+                    STATUS.request_finished(result.is_ok());
+
+                    if result.is_err() {
+                        error!("Rate limit response not written, skipping the request");
+                    }
+
+                    tracker.complete(&tracked);
+
+                    Ok(())
+                });
+
+            tokio::spawn(responding);
+        }
     }
 
-    fn handler(
+    /// Runs `process_event` for every message of the lane streams until
+    /// stopped through `control` or the streams end, reading the lanes in
+    /// their order of priority. Calls `tick` periodically and `finish`
+    /// before exiting.
+    /// With a `grace_period`, the requests in flight are given time to
+    /// finish after the consumption stops and before `finish`.
+    fn handler<S, F, U, T, E>(
         &self,
-        consumer: StreamConsumer,
+        streams: Vec<(S, u32)>,
         control: oneshot::Receiver<()>,
-        process_event: &Fn(BorrowedMessage) -> Result<(), ()>
-    ) -> Result<(), ()> {
+        grace_period: Option<Duration>,
+        tick_interval: Duration,
+        tick: T,
+        finish: E,
+        process_event: F,
+    ) -> Result<(), ()>
+    where
+        S: Stream<Error = ()>,
+        F: Fn(S::Item) -> U,
+        U: IntoFuture<Item = (), Error = ()>,
+        T: Fn(),
+        E: Fn(),
+    {
         let mut core = Runtime::new().unwrap();
 
-        let processed_stream = consumer
-            .start()
-            .filter_map(|result| match result {
-                Ok(msg) => Some(msg),
-                Err(e) => {
-                    warn!("Error while receiving from Kafka: {:?}", e);
-                    None
-                }
-            })
-            .for_each(|msg| process_event(msg))
+        let ticks = Interval::new(Instant::now() + tick_interval, tick_interval)
+            .map_err(|e| warn!("Error in the consumer timer: {:?}", e))
+            .for_each(|_| {
+                tick();
+                Ok(())
+            });
+
+        let processed_stream = Lanes::new(streams, self.lane_mode)
+            .for_each(process_event)
+            .select(ticks)
             .select2(control)
-            .then(|_| consumer.commit_consumer_state(CommitMode::Sync));
+            .then(|_| Ok::<(), ()>(()));
 
         core.block_on(processed_stream).unwrap();
 
+        if let Some(grace_period) = grace_period {
+            core.block_on(Self::drain(grace_period)).unwrap();
+        }
+
+        finish();
+
         Ok(())
     }
 
-    fn handle_push(&self, msg: &BorrowedMessage) {
-        let event_parsing = msg.payload()
-            .and_then(|payload| parse_from_bytes::<PushNotification>(payload).ok());
+    /// Waits for the requests in flight to finish, at most `grace_period`.
+    /// Throttled requests waiting for their turn are in flight, parked
+    /// messages, such as scheduled requests, are not read yet and continue
+    /// from the committed offset after a restart.
+    fn drain(grace_period: Duration) -> impl Future<Item = (), Error = ()> {
+        info!(
+            "Waiting for the requests in flight to finish";
+            "in_flight" => STATUS.in_flight(),
+            "grace_period" => format!("{:?}", grace_period)
+        );
+
+        let finished = Interval::new(Instant::now(), DRAIN_CHECK_INTERVAL)
+            .map_err(|e| warn!("Error in the drain timer: {:?}", e))
+            .take_while(|_| Ok(STATUS.in_flight() > 0))
+            .for_each(|_| Ok(()));
+
+        let timeout = Delay::new(Instant::now() + grace_period)
+            .map_err(|e| warn!("Error in the drain timer: {:?}", e));
+
+        finished.select2(timeout).then(|result| {
+            match result {
+                Ok(Either::A(_)) => info!("All requests finished"),
+                _ => warn!(
+                    "Grace period over, abandoning the requests in flight";
+                    "in_flight" => STATUS.in_flight()
+                ),
+            }
+
+            Ok(())
+        })
+    }
+
+    /// The low and high watermarks of every config topic partition. If the
+    /// brokers can't be reached, the fetch is tried again until it succeeds.
+    /// `None` if stopped through `control` before that.
+    fn config_watermarks(
+        &self,
+        consumer: &StreamConsumer,
+        control: &mut oneshot::Receiver<()>,
+    ) -> Option<Vec<(i64, i64)>> {
+        loop {
+            let fetched = self.config_partitions(consumer).and_then(|partitions| {
+                (0..partitions)
+                    .map(|partition| {
+                        consumer.fetch_watermarks(&self.config_topic, partition, METADATA_TIMEOUT_MS)
+                    })
+                    .collect::<KafkaResult<Vec<(i64, i64)>>>()
+            });
+
+            match fetched {
+                Ok(watermarks) => return Some(watermarks),
+                Err(e) => warn!(
+                    "Couldn't fetch the config topic metadata, retrying";
+                    "config_topic" => &self.config_topic,
+                    "error" => format!("{:?}", e)
+                ),
+            }
+
+            thread::sleep(METADATA_RETRY_INTERVAL);
+
+            match control.try_recv() {
+                Ok(None) => (),
+                _ => return None,
+            }
+        }
+    }
+
+    /// The number of partitions in the config topic.
+    fn config_partitions<C: ConsumerContext + 'static>(
+        &self,
+        consumer: &StreamConsumer<C>,
+    ) -> KafkaResult<i32> {
+        let metadata = consumer.fetch_metadata(Some(&self.config_topic), METADATA_TIMEOUT_MS)?;
+
+        let partitions = metadata
+            .topics()
+            .iter()
+            .find(|topic| topic.name() == self.config_topic)
+            .map(|topic| topic.partitions().len())
+            .unwrap_or(0);
+
+        Ok(partitions as i32)
+    }
+
+    /// Starts consuming partitions added to the config topic from the
+    /// beginning, keeping the old partitions at their `positions`: the next
+    /// offset after the last message read, or where the partition started.
+    fn assign_new_partitions(
+        &self,
+        consumer: &StreamConsumer,
+        partition_count: &Cell<i32>,
+        positions: &RefCell<HashMap<i32, Offset>>,
+    ) {
+        let current = partition_count.get();
+
+        let partitions = match self.config_partitions(consumer) {
+            Ok(partitions) => partitions,
+            Err(e) => {
+                warn!("Couldn't fetch the config topic metadata"; "error" => format!("{:?}", e));
+                return;
+            }
+        };
+
+        if partitions == current {
+            return;
+        }
+
+        if partitions < current {
+            warn!(
+                "Config topic has less partitions than before, ignoring";
+                "partitions" => partitions,
+                "previous_partitions" => current
+            );
+
+            return;
+        }
+
+        let mut positions = positions.borrow_mut();
+
+        for partition in current..partitions {
+            positions.insert(partition, Offset::Beginning);
+        }
+
+        let mut assignment = TopicPartitionList::new();
+
+        for (partition, position) in positions.iter() {
+            assignment.add_partition_offset(&self.config_topic, *partition, *position);
+        }
+
+        match consumer.assign(&assignment) {
+            Ok(()) => {
+                info!(
+                    "Config topic partitions added";
+                    "partitions" => partitions,
+                    "previous_partitions" => current
+                );
+
+                partition_count.set(partitions);
+            }
+            Err(e) => {
+                error!("Couldn't assign the new config partitions"; "error" => format!("{:?}", e));
+            }
+        }
+    }
+
+    /// Logs and sets metrics for the number of applications in every config
+    /// partition, if changed since the last report.
+    fn report_loaded(
+        &self,
+        loaded: &HashMap<i32, HashSet<String>>,
+        reported: &mut HashMap<i32, usize>,
+    ) {
+        let counts: HashMap<i32, usize> = loaded
+            .iter()
+            .map(|(partition, ids)| (*partition, ids.len()))
+            .collect();
+
+        if counts == *reported {
+            return;
+        }
+
+        for (partition, count) in counts.iter() {
+            CONFIG_PARTITION_APPLICATIONS
+                .with_label_values(&[&partition.to_string()])
+                .set(*count as f64);
+        }
+
+        let mut summary: Vec<(&i32, &usize)> = counts.iter().collect();
+        summary.sort();
+
+        info!(
+            "Applications loaded per config partition";
+            "applications" => format!("{:?}", summary)
+        );
+
+        *reported = counts;
+    }
+
+    /// Commits the offsets of the lane handled since the last commit,
+    /// waiting for the commit to finish if `sync`.
+    fn commit_offsets(lane: &InputLane, input: &Input, sync: bool) {
+        if let Some(offsets) = lane.tracker.committable(&lane.topics) {
+            debug!("Committing offsets"; "offsets" => format!("{:?}", offsets));
+
+            if let Err(e) = input.commit(&offsets, sync) {
+                warn!("Error committing offsets"; "error" => e);
+            }
+        }
+    }
+
+    /// Handles the request, completing the offset when the response is
+    /// delivered. If the response couldn't be written, the request goes to
+    /// the dead letter topic and the offset is completed when the dead
+    /// letter is written. Otherwise the offset is kept uncommitted, and the
+    /// request is read again after a restart or a rebalance. Expired
+    /// notifications are not sent, but responded with
+    /// `ResponseAction::Expired`. Duplicates of a notification are responded
+    /// with its final result, or skipped if it's still in flight.
+    fn dispatch(
+        dispatcher: Dispatcher<H>,
+        tracked: TrackedOffset,
+        request: Request,
+        permit: Permit,
+    ) -> impl Future<Item = (), Error = ()> + 'static + Send {
+        let Dispatcher { handler, responses, dead_letters, dedupe, tracker } = dispatcher;
+
+        let dead_letter = dead_letters
+            .map(|dead_letters| (dead_letters, request.routing().clone(), request.payload()));
+
+        let now = Utc::now().timestamp_millis();
+        let mut deduped = None;
+
+        let handling: Delivery = match request {
+            Request::Push(mut routing, event) => {
+                // Retries are the same notification coming back on purpose.
+                let seen = match dedupe {
+                    Some(ref window) if routing.attempt == 0 => {
+                        let seen = window.check(&event);
+
+                        if seen == Seen::New {
+                            deduped = Deduped::new(window.clone(), &event);
+                            routing.dedupe = deduped.clone();
+                        }
+
+                        seen
+                    }
+                    _ => Seen::New,
+                };
+
+                match (seen, event.expires_at()) {
+                    (Seen::InFlight, _) => {
+                        debug!("Duplicate of a notification in flight, skipping"; &event);
+                        DUPLICATES_COUNTER.with_label_values(&["in_flight"]).inc();
 
-        match event_parsing {
-            Some(event) => {
+                        Box::new(future::ok(()))
+                    }
+                    (Seen::Responded(action), _) => {
+                        info!(
+                            "Duplicate notification, responding with the previous result";
+                            &event,
+                            "response_action" => format!("{:?}", action)
+                        );
+
+                        DUPLICATES_COUNTER.with_label_values(&["responded"]).inc();
+                        responses.publish_result(routing, event, action)
+                    }
+                    (Seen::New, Some(expires_at)) if expires_at < now => {
+                        info!(
+                            "Push notification expired, not sending";
+                            &event,
+                            "expired_ms_ago" => now - expires_at
+                        );
+
+                        EXPIRED_COUNTER.inc();
+                        responses.respond(routing, event, ResponseAction::Expired)
+                    }
+                    (Seen::New, _) => handler.handle_notification(routing, event),
+                }
+            }
+            Request::Http(routing, event) =>
+                handler.handle_http(routing, event),
+        };
+
+        handling.then(move |result| {
+            drop(permit);
+            STATUS.request_finished(result.is_ok());
+
+            if result.is_ok() {
+                tracker.complete(&tracked);
+                return Either::A(future::ok(()));
+            }
+
+            if let Some(deduped) = deduped {
+                deduped.forget();
+            }
+
+            match dead_letter {
+                Some((dead_letters, routing, payload)) => {
+                    error!("Response not written, sending the request to the dead letter topic");
+
+                    let dead_lettering = dead_letters
+                        .publish_request(&tracked, &routing, &payload, "response_not_written")
+                        .then(move |result| {
+                            match result {
+                                Ok(()) => tracker.complete(&tracked),
+                                Err(()) => error!(
+                                    "Dead letter not written, keeping the offset uncommitted"
+                                ),
+                            }
+
+                            Ok(())
+                        });
+
+                    Either::B(dead_lettering)
+                }
+                None => {
+                    error!("Response not written, keeping the offset uncommitted");
+                    Either::A(future::ok(()))
+                }
+            }
+        })
+    }
+
+    fn routing(msg: &Incoming) -> Routing {
+        let attempt = msg.header(RETRY_ATTEMPT_HEADER)
+            .and_then(|attempt| attempt.parse().ok())
+            .unwrap_or(0);
+
+        let headers = msg.headers
+            .iter()
+            .filter(|&&(ref key, _)| {
+                key != RETRY_ATTEMPT_HEADER && key != RETRY_AT_HEADER && key != SCHEDULE_AT_HEADER
+            })
+            .cloned()
+            .collect();
+
+        Routing {
+            key: msg.key.clone(),
+            attempt,
+            headers,
+            reply_to: None,
+        }
+    }
+
+    /// The topic for the response from the `reply_to` field of the rpc
+    /// header, or from the `reply_to` Kafka header. `None` if not requested
+    /// or not in `reply_topics`, the response then goes to the output topic.
+    fn reply_to(&self, requested: &str, routing: &Routing) -> Option<String> {
+        let requested = if requested.is_empty() {
+            routing.header(REPLY_TO_HEADER)?
+        } else {
+            requested
+        };
+
+        if self.reply_topics.iter().any(|topic| topic == requested) {
+            Some(requested.to_string())
+        } else {
+            warn!(
+                "Reply topic not allowed, responding to the output topic";
+                "reply_to" => requested
+            );
+
+            REPLY_TO_REJECTED_COUNTER.inc();
+
+            None
+        }
+    }
+
+    /// The time in the header, in milliseconds since the epoch, when the
+    /// request should be handled: `RETRY_AT_HEADER` for messages from the
+    /// retry topics, `SCHEDULE_AT_HEADER` for messages from the schedule
+    /// topics. `None` if the message can be handled right away.
+    fn due_at(msg: &Incoming, header: &str) -> Option<Instant> {
+        Self::wait_ms(msg, header).map(|wait_ms| Instant::now() + Duration::from_millis(wait_ms))
+    }
+
+    /// Milliseconds until the time in the header, `None` if not set or
+    /// already passed.
+    fn wait_ms(msg: &Incoming, header: &str) -> Option<u64> {
+        let due_at: i64 = msg.header(header)
+            .and_then(|due_at| due_at.parse().ok())?;
+
+        let wait_ms = due_at - Utc::now().timestamp_millis();
+
+        if wait_ms > 0 {
+            Some(wait_ms as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Decodes a `PushNotification`, `Ok(None)` if the event is for some
+    /// other consumer and `Err` with a reason if the message is invalid.
+    fn parse_push(&self, msg: &Incoming) -> Result<Option<Request>, String> {
+        match Self::decode::<PushNotification>(msg) {
+            Ok(event) => {
                 if self.handler.accepts(&event) {
-                    let notification_handling = self.handler.handle_notification(
-                        msg.key().map(|key| key.to_vec()),
-                        event
-                    );
+                    let mut routing = Self::routing(msg);
+                    routing.reply_to = self.reply_to(event.get_header().get_reply_to(), &routing);
 
-                    tokio::spawn(notification_handling);
+                    Ok(Some(Request::Push(routing, event)))
                 } else {
                     debug!("Push notification skipped");
+                    Ok(None)
                 }
             }
-            None => {
-                error!("Error parsing a PushNotification event");
+            Err(e) => {
+                error!("Error parsing a PushNotification event"; "error" => e.as_str());
+                Err(format!("invalid_push_notification: {}", e))
             }
         }
     }
 
-    fn handle_http(&self, msg: &BorrowedMessage) {
-        let event_parsing = msg.payload()
-            .and_then(|payload| parse_from_bytes::<HttpRequest>(payload).ok());
+    /// Decodes a `HttpRequest`, `Err` with a reason if the message is
+    /// invalid.
+    fn parse_http(&self, msg: &Incoming) -> Result<Option<Request>, String> {
+        match Self::decode::<HttpRequest>(msg) {
+            Ok(event) => {
+                let mut routing = Self::routing(msg);
+                routing.reply_to = self.reply_to(event.get_header().get_reply_to(), &routing);
 
-        match event_parsing {
-            Some(event) => {
-                let http_handling = self.handler.handle_http(
-                    msg.key().map(|key| key.to_vec()),
-                    event
-                );
-
-                tokio::spawn(http_handling);
+                Ok(Some(Request::Http(routing, event)))
             }
-            None => {
-                debug!("Not a HttpRequest event this one here");
+            Err(e) => {
+                debug!("Not a HttpRequest event this one here"; "error" => e.as_str());
+                Err(format!("invalid_http_request: {}", e))
+            }
+        }
+    }
+
+    /// Decodes the payload, `Err` with the protobuf error if invalid.
+    fn decode<M: ProtobufMessage>(msg: &Incoming) -> Result<M, String> {
+        if msg.payload.is_empty() {
+            Err(String::from("empty payload"))
+        } else {
+            parse_from_bytes::<M>(&msg.payload).map_err(|e| e.to_string())
+        }
+    }
+
+    /// Configures the tenant of an `Application` event, or removes the
+    /// configuration of the tenant in the key of a message with an empty
+    /// payload. False if the message is not a configuration.
+    fn configure_inline(&self, msg: &Incoming) -> bool {
+        if msg.payload.is_empty() {
+            let id = msg.key
+                .as_ref()
+                .and_then(|key| str::from_utf8(key).ok());
+
+            return match id {
+                Some(id) => {
+                    debug!("Got null configuration"; "universe" => id);
+                    self.configure(id, None);
+
+                    true
+                }
+                None => false,
+            };
+        }
+
+        let is_application = Self::decode::<RequestWrapper>(msg)
+            .map(|decoder| decoder.get_header().get_field_type() == "application.Application")
+            .unwrap_or(false);
+
+        if !is_application {
+            return false;
+        }
+
+        match Self::decode::<Application>(msg) {
+            Ok(application) => {
+                let id = application.get_id().to_string();
+                debug!("Got application configuration"; "universe" => &id);
+
+                self.configure(&id, Some(application));
             }
+            Err(e) => error!("Error parsing an Application event"; "error" => e),
         }
+
+        true
     }
 
-    fn handle_config(&self, msg_id: &str, msg: Option<&BorrowedMessage>) {
-        let event = msg
-            .and_then(|msg| msg.payload())
-            .and_then(|payload| parse_from_bytes::<Application>(&payload).ok());
+    fn handle_config(&self, msg_id: &str, payload: Option<&[u8]>) {
+        let event = payload
+            .and_then(|payload| parse_from_bytes::<Application>(payload).ok());
+
+        self.configure(msg_id, event);
+    }
 
-        self.handler.handle_config(msg_id, event);
+    fn configure(&self, id: &str, config: Option<Application>) {
+        self.rate_limiter.configure(id, config.as_ref());
+        self.handler.handle_config(id, config);
     }
 }
@@ -1,68 +1,190 @@
-use rdkafka::{
-    config::ClientConfig,
-    producer::future_producer::{
-        DeliveryFuture,
-        FutureProducer,
-        FutureRecord,
+use events::{
+    push_notification::PushNotification,
+    push_result::{
+        PushResult,
+        PushResult_ResponseAction as ResponseAction,
     },
 };
-
-use kafka::Config;
+use kafka::{Config, RetryTopic, Routing};
+use metrics::RETRIES_COUNTER;
 use protobuf::Message;
 use std::sync::Arc;
+use chrono::Utc;
+use transport::{Delivery, Record, Transport};
+
+/// Header for the number of times the notification has been retried.
+pub const RETRY_ATTEMPT_HEADER: &str = "retry_attempt";
+/// Header for the time, in milliseconds since the epoch, when the retry
+/// should be sent.
+pub const RETRY_AT_HEADER: &str = "retry_at";
 
-struct Kafka {
+struct Inner {
     output_topic: String,
-    producer: FutureProducer,
+    retry_topics: Vec<RetryTopic>,
+    forward_headers: Vec<String>,
+    transport: Arc<Transport>,
 }
 
+#[derive(Clone)]
 pub struct ResponseProducer {
-    kafka: Arc<Kafka>,
+    inner: Arc<Inner>,
 }
 
 impl ResponseProducer {
-    /// Producer to send responses to notification events.
-    pub fn new(config: &Config) -> ResponseProducer {
-        let producer = ClientConfig::new()
-            .set("bootstrap.servers", &config.brokers)
-            .set("produce.offset.report", "true")
-            .create()
-            .expect("Producer creation error");
-
-        let kafka = Arc::new(Kafka {
+    /// Producer to send responses to notification events through the
+    /// transport.
+    pub fn new(config: &Config, transport: Arc<Transport>) -> ResponseProducer {
+        let inner = Arc::new(Inner {
             output_topic: config.output_topic.clone(),
-            producer,
+            retry_topics: config.retry_topics.clone(),
+            forward_headers: config.forward_headers.clone(),
+            transport,
         });
 
-        ResponseProducer { kafka }
+        ResponseProducer { inner }
     }
 
-    /// Send the push response. If key is set, sets the routing key in the Kafka
-    /// message.
+    /// Send the push response to the reply topic of the request or to the
+    /// output topic. If the routing has a key, sets the routing key in the
+    /// record. The request headers in `forward_headers` are copied to
+    /// the response.
     pub fn publish(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: &Message,
-    ) -> DeliveryFuture {
+    ) -> Delivery {
+        let payload = event.write_to_bytes().unwrap();
+
+        let headers = routing.headers
+            .iter()
+            .filter(|&&(ref name, _)| self.inner.forward_headers.contains(name))
+            .cloned()
+            .collect();
+
+        let topic = routing.reply_to
+            .unwrap_or_else(|| self.inner.output_topic.clone());
+
+        let record = Record {
+            topic,
+            key: routing.key,
+            headers,
+            payload,
+        };
+
+        self.inner.transport.send(record)
+    }
+
+    /// Respond to a push notification with the given action. A notification
+    /// to be retried goes to the next retry topic, and the response is sent
+    /// only when there are no retry topics left. Only a final action is
+    /// stored for the duplicates of the notification.
+    pub fn respond(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        response_action: ResponseAction,
+    ) -> Delivery {
+        self.respond_with(routing, event, response_action, |_| ())
+    }
+
+    /// Like `respond`, letting `decorate` add system-specific fields to the
+    /// `PushResult`.
+    pub fn respond_with<F>(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        response_action: ResponseAction,
+        decorate: F,
+    ) -> Delivery
+    where
+        F: FnOnce(&mut PushResult),
+    {
+        if let Some(ref deduped) = routing.dedupe {
+            deduped.responded(response_action);
+        }
+
+        if response_action == ResponseAction::Retry {
+            let retry_topic = self.inner.retry_topics.get(routing.attempt as usize);
+
+            if let Some(retry_topic) = retry_topic {
+                return self.enqueue_retry(routing, event, retry_topic);
+            }
+
+            if routing.attempt > 0 {
+                warn!(
+                    "No retries left for the push notification";
+                    &event,
+                    "attempts" => routing.attempt
+                );
+            }
+        }
+
+        let mut result: PushResult = (event, response_action).into();
+        decorate(&mut result);
+
+        self.publish(routing, &result)
+    }
+
+    /// Sends the `PushResult` with the given action as-is.
+    pub fn publish_result(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        response_action: ResponseAction,
+    ) -> Delivery {
+        let result: PushResult = (event, response_action).into();
+        self.publish(routing, &result)
+    }
+
+    /// Sends the notification to the retry topic with all the request
+    /// headers.
+    fn enqueue_retry(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+        retry_topic: &RetryTopic,
+    ) -> Delivery {
+        let attempt = routing.attempt + 1;
+        let retry_at = Utc::now().timestamp_millis() + retry_topic.delay_ms as i64;
+
+        info!(
+            "Retrying the push notification";
+            &event,
+            "attempt" => attempt,
+            "retry_topic" => &retry_topic.topic
+        );
+
+        RETRIES_COUNTER.with_label_values(&[&attempt.to_string()]).inc();
+
         let payload = event.write_to_bytes().unwrap();
 
-        let record = FutureRecord {
-            topic: &self.kafka.output_topic,
-            partition: None,
-            payload: Some(&payload),
-            key: key.as_ref(),
-            timestamp: None,
-            headers: None,
+        let mut headers = routing.headers;
+        headers.push((RETRY_ATTEMPT_HEADER.to_string(), attempt.to_string().into_bytes()));
+        headers.push((RETRY_AT_HEADER.to_string(), retry_at.to_string().into_bytes()));
+
+        let record = Record {
+            topic: retry_topic.topic.clone(),
+            key: routing.key,
+            headers,
+            payload,
         };
 
-        self.kafka.producer.send::<Vec<u8>, Vec<u8>>(record, -1)
+        self.inner.transport.send(record)
     }
 }
 
-impl Clone for ResponseProducer {
-    fn clone(&self) -> Self {
-        ResponseProducer {
-            kafka: self.kafka.clone(),
+/// Maps the result of a `DeliveryFuture` into the result of the event
+/// handling. Fails if the response was not written to Kafka.
+pub fn delivered<T, E, C>(result: Result<Result<T, E>, C>) -> Result<(), ()> {
+    match result {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(_)) => {
+            error!("Couldn't write the response to Kafka");
+            Err(())
+        }
+        Err(_) => {
+            error!("Response delivery was canceled");
+            Err(())
         }
     }
 }
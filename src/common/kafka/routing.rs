@@ -0,0 +1,42 @@
+use kafka::dedupe::Deduped;
+use std::str;
+
+/// Kafka header for the topic where the response should be sent.
+pub const REPLY_TO_HEADER: &str = "reply_to";
+
+/// Kafka header for the time, in milliseconds since the epoch, when the
+/// request should be handled.
+pub const SEND_AT_HEADER: &str = "send_at";
+
+/// Kafka header for the time, in milliseconds since the epoch, when a
+/// scheduled request goes on from its schedule topic.
+pub const SCHEDULE_AT_HEADER: &str = "schedule_at";
+
+/// Routing information of an incoming request, used when responding.
+#[derive(Debug, Clone, Default)]
+pub struct Routing {
+    /// The Kafka key of the request. If set, the response is sent with the
+    /// same key.
+    pub key: Option<Vec<u8>>,
+    /// The number of times the request has gone through the retry topics.
+    pub attempt: u32,
+    /// The Kafka headers of the request, without the ones used internally
+    /// for retries and scheduling.
+    pub headers: Vec<(String, Vec<u8>)>,
+    /// The topic for the response, if requested and allowed. Otherwise the
+    /// response goes to the output topic.
+    pub reply_to: Option<String>,
+    /// The notification in the dedupe window, if enabled, to store the
+    /// response for its duplicates.
+    pub dedupe: Option<Deduped>,
+}
+
+impl Routing {
+    /// The first request header with the given name, if valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .and_then(|&(_, ref value)| str::from_utf8(value).ok())
+    }
+}
@@ -7,6 +7,7 @@
 
 extern crate a2;
 extern crate argparse;
+extern crate base64;
 extern crate chan_signal;
 extern crate chrono;
 extern crate erased_serde;
@@ -16,6 +17,7 @@ extern crate hyper;
 extern crate protobuf;
 extern crate rdkafka;
 extern crate serde;
+extern crate serde_json;
 extern crate tokio;
 extern crate toml;
 extern crate web_push;
@@ -29,4 +31,7 @@ pub mod events;
 pub mod kafka;
 pub mod logger;
 pub mod metrics;
+pub mod status;
 pub mod system;
+pub mod tenants;
+pub mod transport;
@@ -24,16 +24,40 @@ impl Logger {
     /// Builds a new logger. Depending on `LOG_FORMAT` environment variable,
     /// either produces colorful text or JSON.
     pub fn build(application_name: &'static str) -> slog::Logger {
-        let drain = match env::var("LOG_FORMAT") {
-            Ok(ref val) if val == "json" => {
+        Self::build_to(application_name, false)
+    }
+
+    /// Builds a new logger writing to stderr, keeping stdout free for the
+    /// responses of the stdio transport.
+    pub fn build_stderr(application_name: &'static str) -> slog::Logger {
+        Self::build_to(application_name, true)
+    }
+
+    fn build_to(application_name: &'static str, stderr: bool) -> slog::Logger {
+        let json = match env::var("LOG_FORMAT") {
+            Ok(ref val) if val == "json" => true,
+            _ => false,
+        };
+
+        let drain = match (json, stderr) {
+            (true, false) => {
                 let drain = Json::new(io::stdout()).add_default_keys().build().fuse();
                 Async::new(drain).build().fuse()
             }
-            _ => {
+            (true, true) => {
+                let drain = Json::new(io::stderr()).add_default_keys().build().fuse();
+                Async::new(drain).build().fuse()
+            }
+            (false, false) => {
                 let decorator = TermDecorator::new().stdout().build();
                 let drain = CompactFormat::new(decorator).build().fuse();
                 Async::new(drain).build().fuse()
             }
+            (false, true) => {
+                let decorator = TermDecorator::new().stderr().build();
+                let drain = CompactFormat::new(decorator).build().fuse();
+                Async::new(drain).build().fuse()
+            }
         };
 
         let environment = env::var("RUST_ENV")
@@ -1,8 +1,12 @@
-use http::header;
-use prometheus::{self, CounterVec, Encoder, Gauge, Histogram, TextEncoder};
+use http::{header, StatusCode};
+use prometheus::{self, Counter, CounterVec, Encoder, Gauge, GaugeVec, Histogram, TextEncoder};
+use status::STATUS;
+use tenants::TENANTS;
+use serde::Serialize;
+use serde_json;
 use std::env;
 
-use hyper::{rt, Body, Request, Response, Server, service::service_fn_ok};
+use hyper::{rt, Body, Method, Request, Response, Server, service::service_fn_ok};
 
 use futures::{Future, sync::oneshot::Receiver};
 
@@ -12,10 +16,42 @@ lazy_static! {
         "Total number of push notifications responded.",
         &["status"]
     ).unwrap();
+    pub static ref RETRIES_COUNTER: CounterVec = register_counter_vec!(
+        "push_notifications_retries_total",
+        "Total number of push notifications sent to a retry topic.",
+        &["attempt"]
+    ).unwrap();
+    pub static ref REPLY_TO_REJECTED_COUNTER: Counter = register_counter!(
+        "push_notifications_reply_to_rejected_total",
+        "Total number of requests with a reply topic not in the allowed reply topics."
+    ).unwrap();
+    pub static ref EXPIRED_COUNTER: Counter = register_counter!(
+        "push_notifications_expired_total",
+        "Total number of push notifications expired before sending."
+    ).unwrap();
+    pub static ref DUPLICATES_COUNTER: CounterVec = register_counter_vec!(
+        "push_notifications_duplicates_total",
+        "Total number of push notifications skipped as duplicates.",
+        &["state"]
+    ).unwrap();
+    pub static ref THROTTLED_COUNTER: CounterVec = register_counter_vec!(
+        "push_notifications_throttled_total",
+        "Total number of push notifications over the tenant rate limit.",
+        &["universe", "outcome"]
+    ).unwrap();
     pub static ref CALLBACKS_INFLIGHT: Gauge = register_gauge!(
         "push_notifications_in_flight",
         "Number of push notifications in flight"
     ).unwrap();
+    pub static ref CONSUMER_SATURATED: Gauge = register_gauge!(
+        "push_notifications_consumer_saturated",
+        "1 if the consumer is paused due to too many requests in flight"
+    ).unwrap();
+    pub static ref TENANT_SATURATED: GaugeVec = register_gauge_vec!(
+        "push_notifications_tenant_saturated",
+        "1 if partitions are paused due to too many requests in flight for the tenant",
+        &["universe"]
+    ).unwrap();
     pub static ref RESPONSE_TIMES_HISTOGRAM: Histogram = register_histogram!(
         "http_request_latency_seconds",
         "The HTTP request latencies in seconds"
@@ -28,6 +64,16 @@ lazy_static! {
         "apns_certificate_consumers",
         "Number of certificate-based consumers to Apple push notification service"
     ).unwrap();
+    pub static ref SANDBOX_FALLBACK_COUNTER: CounterVec = register_counter_vec!(
+        "apns_sandbox_fallback_total",
+        "Total number of notifications sent again to the APNs sandbox after BadDeviceToken from production.",
+        &["status"]
+    ).unwrap();
+    pub static ref CONFIG_PARTITION_APPLICATIONS: GaugeVec = register_gauge_vec!(
+        "config_partition_applications",
+        "Number of applications loaded from a partition of the config topic",
+        &["partition"]
+    ).unwrap();
     pub static ref NUMBER_OF_APPLICATIONS: Gauge = register_gauge!(
         "push_notications_number_of_applications",
         "Number of applications sending push notifications"
@@ -38,7 +84,79 @@ lazy_static! {
 pub struct StatisticsServer;
 
 impl StatisticsServer {
-    fn prometheus(_: Request<Body>) -> Response<Body> {
+    fn route(req: Request<Body>) -> Response<Body> {
+        match (req.method(), req.uri().path()) {
+            (&Method::GET, "/metrics") => Self::prometheus(),
+            (&Method::GET, "/healthz") => Self::healthz(),
+            (&Method::GET, "/ready") => Self::ready(),
+            (&Method::GET, "/admin/tenants") => Self::json(&TENANTS.list()),
+            (&Method::GET, path) if path.starts_with("/admin/tenants/") => {
+                match TENANTS.get(&path["/admin/tenants/".len()..]) {
+                    Some(tenant) => Self::json(&tenant),
+                    None => Self::not_found(),
+                }
+            }
+            _ => Self::not_found(),
+        }
+    }
+
+    fn json<T: Serialize>(value: &T) -> Response<Body> {
+        let mut builder = Response::builder();
+
+        builder.header(header::CONTENT_TYPE, "application/json");
+        builder.body(serde_json::to_vec(value).unwrap().into()).unwrap()
+    }
+
+    /// `200 OK` if the request and config consumers are running and
+    /// processing, otherwise `503 Service Unavailable`.
+    fn healthz() -> Response<Body> {
+        let body = format!(
+            concat!(
+                "consumer_running: {}\nconfig_consumer_stopped: {}\n",
+                "requests_in_flight: {}\nseconds_since_last_processed: {}\n"
+            ),
+            STATUS.consumer_running(),
+            STATUS.config_consumer_stopped(),
+            STATUS.in_flight(),
+            STATUS.since_last_progress().as_secs(),
+        );
+
+        Self::status_response(STATUS.is_healthy(), body)
+    }
+
+    /// `200 OK` when the system can handle requests, otherwise
+    /// `503 Service Unavailable`.
+    fn ready() -> Response<Body> {
+        let body = format!(
+            "config_replayed: {}\nproducer_connected: {}\n",
+            STATUS.config_replayed(),
+            STATUS.producer_connected(),
+        );
+
+        Self::status_response(STATUS.is_ready(), body)
+    }
+
+    fn status_response(ok: bool, body: String) -> Response<Body> {
+        let mut builder = Response::builder();
+
+        if ok {
+            builder.status(StatusCode::OK);
+        } else {
+            builder.status(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        builder.header(header::CONTENT_TYPE, "text/plain");
+        builder.body(body.into()).unwrap()
+    }
+
+    fn not_found() -> Response<Body> {
+        let mut builder = Response::builder();
+
+        builder.status(StatusCode::NOT_FOUND);
+        builder.body(Body::empty()).unwrap()
+    }
+
+    fn prometheus() -> Response<Body> {
         let encoder = TextEncoder::new();
         let metric_families = prometheus::gather();
         let mut buffer = vec![];
@@ -60,7 +178,7 @@ impl StatisticsServer {
         let addr = format!("0.0.0.0:{}", port).parse().unwrap();
 
         let server = Server::bind(&addr)
-            .serve(|| service_fn_ok(Self::prometheus))
+            .serve(|| service_fn_ok(Self::route))
             .map_err(|e| eprintln!("server error: {}", e));
 
         rt::run(server.select2(rx).then(move |_| Ok(())));
@@ -0,0 +1,136 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// If requests are in flight and none of them finished in this time, the
+/// consumer is considered unhealthy.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// The state of the system, reported by the statistics server.
+    pub static ref STATUS: Status = Status::new();
+}
+
+pub struct Status {
+    config_replayed: AtomicBool,
+    producer_connected: AtomicBool,
+    consumer_running: AtomicBool,
+    config_consumer_stopped: AtomicBool,
+    in_flight: AtomicUsize,
+    last_progress: Mutex<Instant>,
+}
+
+/// Marks the request consumer running until dropped.
+pub struct ConsumerRunning;
+
+impl Drop for ConsumerRunning {
+    fn drop(&mut self) {
+        STATUS.consumer_running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Marks the config consumer stopped when dropped, also when its thread
+/// panics.
+pub struct ConfigConsumerRunning;
+
+impl Drop for ConfigConsumerRunning {
+    fn drop(&mut self) {
+        STATUS.config_consumer_stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Status {
+    fn new() -> Status {
+        Status {
+            config_replayed: AtomicBool::new(false),
+            producer_connected: AtomicBool::new(false),
+            consumer_running: AtomicBool::new(false),
+            config_consumer_stopped: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            last_progress: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// True when the config consumer has read the config topic to the end
+    /// and all the applications are loaded.
+    pub fn config_replayed(&self) -> bool {
+        self.config_replayed.load(Ordering::SeqCst)
+    }
+
+    pub fn set_config_replayed(&self) {
+        self.config_replayed.store(true, Ordering::SeqCst);
+    }
+
+    /// True if the response producer can reach the Kafka brokers.
+    pub fn producer_connected(&self) -> bool {
+        self.producer_connected.load(Ordering::SeqCst)
+    }
+
+    pub fn set_producer_connected(&self, connected: bool) {
+        self.producer_connected.store(connected, Ordering::SeqCst);
+    }
+
+    /// True while the request consumer thread is consuming.
+    pub fn consumer_running(&self) -> bool {
+        self.consumer_running.load(Ordering::SeqCst)
+    }
+
+    /// Sets the consumer running until the returned guard is dropped.
+    pub fn start_consumer(&self) -> ConsumerRunning {
+        self.consumer_running.store(true, Ordering::SeqCst);
+        *self.last_progress.lock().unwrap() = Instant::now();
+
+        ConsumerRunning
+    }
+
+    /// True if the config consumer thread has exited.
+    pub fn config_consumer_stopped(&self) -> bool {
+        self.config_consumer_stopped.load(Ordering::SeqCst)
+    }
+
+    /// Sets the config consumer stopped when the returned guard is dropped.
+    pub fn start_config_consumer(&self) -> ConfigConsumerRunning {
+        ConfigConsumerRunning
+    }
+
+    /// A request is taken into processing.
+    pub fn request_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// A request is processed, `successful` if the response got delivered.
+    pub fn request_finished(&self, successful: bool) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        if successful {
+            *self.last_progress.lock().unwrap() = Instant::now();
+        }
+    }
+
+    /// Time since the last successfully processed request, or since the
+    /// consumer was started.
+    pub fn since_last_progress(&self) -> Duration {
+        self.last_progress.lock().unwrap().elapsed()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// True if the request consumer is running and not stuck with requests
+    /// in flight, and the config consumer hasn't stopped.
+    pub fn is_healthy(&self) -> bool {
+        let stalled = self.in_flight() > 0 && self.since_last_progress() > STALL_TIMEOUT;
+
+        self.consumer_running() && !self.config_consumer_stopped() && !stalled
+    }
+
+    /// True if the system can handle requests.
+    pub fn is_ready(&self) -> bool {
+        self.config_replayed() && self.producer_connected()
+    }
+}
@@ -3,37 +3,53 @@ use config::Config;
 use kafka::EventHandler;
 use kafka::RequestConsumer;
 use metrics::StatisticsServer;
+use status::STATUS;
 use std::{thread, thread::JoinHandle, sync::Arc};
 use futures::sync::oneshot;
 use logger::Logger;
 use slog_scope;
+use transport::{Transport, TransportType};
 
 pub struct System;
 
 impl System {
-    /// Starts the push system for given handler and configuration.
-    pub fn start<H>(name: &'static str, handler: H, config: &Config)
+    /// Starts the push system for given handler, transport and
+    /// configuration. The handler should respond through the same transport.
+    pub fn start<H>(
+        name: &'static str,
+        handler: H,
+        transport: Arc<Transport>,
+        config: &Config,
+    )
     where
         H: EventHandler + Send + Sync + 'static,
     {
         let exit_signal = notify(&[Signal::INT, Signal::TERM]);
         let (server_tx, server_rx) = oneshot::channel();
-        let (request_tx, request_rx) = oneshot::channel();
-        let (config_tx, config_rx) = oneshot::channel();
 
-        let logger = Logger::build(name);
+        let logger = match config.transport {
+            TransportType::Stdio => Logger::build_stderr(name),
+            TransportType::Kafka => Logger::build(name),
+        };
+
         let _log_guard = slog_scope::set_global_logger(logger);
 
         slog_scope::scope(&slog_scope::logger().new(slog_o!()), || {
             info!("Bringing up the system");
 
             let mut threads: Vec<JoinHandle<_>> = Vec::new();
-            let consumer = Arc::new(RequestConsumer::new(handler, &config.kafka));
+            let mut controls = vec![server_tx];
+
+            let (request_tx, request_rx) = oneshot::channel();
+            let consumer = Arc::new(RequestConsumer::new(handler, transport, &config.kafka));
+
+            controls.push(request_tx);
 
             threads.push({
                 let consumer = consumer.clone();
                 thread::spawn(move || {
                     info!("Starting the request consumer");
+                    let _running = STATUS.start_consumer();
 
                     if let Err(error) = consumer.handle_requests(request_rx) {
                         error!("Error in request consumer"; "error" => format!("{:?}", error));
@@ -43,18 +59,27 @@ impl System {
                 })
             });
 
-            threads.push({
-                let consumer = consumer.clone();
-                thread::spawn(move || {
-                    info!("Starting the config consumer");
+            // The local transports have the tenant configuration in between
+            // the requests.
+            if config.transport == TransportType::Kafka {
+                let (config_tx, config_rx) = oneshot::channel();
 
-                    if let Err(error) = consumer.handle_configs(config_rx) {
-                        error!("Error in config consumer"; "error" => format!("{:?}", error));
-                    }
+                controls.push(config_tx);
 
-                    info!("Exiting config consumer");
-                })
-            });
+                threads.push({
+                    let consumer = consumer.clone();
+                    thread::spawn(move || {
+                        info!("Starting the config consumer");
+                        let _running = STATUS.start_config_consumer();
+
+                        if let Err(error) = consumer.handle_configs(config_rx) {
+                            error!("Error in config consumer"; "error" => format!("{:?}", error));
+                        }
+
+                        info!("Exiting config consumer");
+                    })
+                });
+            }
 
             threads.push({
                 thread::spawn(move || {
@@ -68,9 +93,11 @@ impl System {
                 exit_signal.recv() -> signal => {
                     info!("Received signal"; "signal" => format!("{:?}", signal));
 
-                    server_tx.send(()).unwrap();
-                    request_tx.send(()).unwrap();
-                    config_tx.send(()).unwrap();
+                    for control in controls {
+                        // The consumer might have stopped already when its
+                        // input closed.
+                        let _ = control.send(());
+                    }
 
                     for thread in threads {
                         thread.thread().unpark();
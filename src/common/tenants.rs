@@ -0,0 +1,117 @@
+use chrono::Utc;
+use std::{collections::HashMap, sync::RwLock};
+
+lazy_static! {
+    /// The loaded tenants, reported by the admin API.
+    pub static ref TENANTS: Tenants = Tenants::new();
+}
+
+/// How the consumer connects to the push service for a tenant. Secrets must
+/// be passed through `redact`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Connection {
+    /// E.g. `token`, `certificate` or `api_key`.
+    pub connection_type: String,
+    /// `sandbox` or `production` for APNs.
+    pub endpoint: Option<String>,
+    /// The APNs topic.
+    pub topic: Option<String>,
+    /// Identifies the credentials without revealing them.
+    pub credentials: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Tenant {
+    pub universe: String,
+    pub connection: Connection,
+    pub loaded_at: String,
+    pub last_success_at: Option<String>,
+    pub last_error_at: Option<String>,
+    pub last_error: Option<String>,
+}
+
+pub struct Tenants {
+    tenants: RwLock<HashMap<String, Tenant>>,
+}
+
+/// Shows only the end of a secret.
+pub fn redact(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+
+    if chars.len() > 12 {
+        let tail: String = chars[chars.len() - 4..].iter().collect();
+        format!("****{}", tail)
+    } else {
+        String::from("****")
+    }
+}
+
+impl Tenants {
+    fn new() -> Tenants {
+        Tenants {
+            tenants: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// A tenant configuration is loaded or updated.
+    pub fn loaded(&self, universe: &str, connection: Connection) {
+        self.insert(universe, connection, None);
+    }
+
+    /// A tenant configuration couldn't be loaded, e.g. due to invalid
+    /// credentials.
+    pub fn failed(&self, universe: &str, connection: Connection, reason: &str) {
+        self.insert(universe, connection, Some(reason));
+    }
+
+    fn insert(&self, universe: &str, connection: Connection, error: Option<&str>) {
+        let now = Utc::now().to_rfc3339();
+
+        let tenant = Tenant {
+            universe: universe.to_string(),
+            connection,
+            loaded_at: now.clone(),
+            last_success_at: None,
+            last_error_at: error.map(|_| now),
+            last_error: error.map(|reason| reason.to_string()),
+        };
+
+        self.tenants.write().unwrap().insert(universe.to_string(), tenant);
+    }
+
+    pub fn removed(&self, universe: &str) {
+        self.tenants.write().unwrap().remove(universe);
+    }
+
+    /// A request for the tenant succeeded.
+    pub fn success(&self, universe: &str) {
+        if let Some(tenant) = self.tenants.write().unwrap().get_mut(universe) {
+            tenant.last_success_at = Some(Utc::now().to_rfc3339());
+        }
+    }
+
+    /// A request for the tenant failed with `reason`.
+    pub fn error(&self, universe: &str, reason: &str) {
+        if let Some(tenant) = self.tenants.write().unwrap().get_mut(universe) {
+            tenant.last_error_at = Some(Utc::now().to_rfc3339());
+            tenant.last_error = Some(reason.to_string());
+        }
+    }
+
+    pub fn get(&self, universe: &str) -> Option<Tenant> {
+        self.tenants.read().unwrap().get(universe).cloned()
+    }
+
+    /// All the tenants, sorted by universe.
+    pub fn list(&self) -> Vec<Tenant> {
+        let mut tenants: Vec<Tenant> = self.tenants
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect();
+
+        tenants.sort_by(|a, b| a.universe.cmp(&b.universe));
+        tenants
+    }
+}
@@ -0,0 +1,59 @@
+use futures::{future, sync::mpsc};
+use transport::{Delivery, Incoming, Input, LocalInput, Messages, Record, Revoked, Transport};
+
+use std::sync::Mutex;
+
+/// An in-memory transport, for running the handlers without a broker.
+pub struct ChannelTransport {
+    requests: Mutex<Option<mpsc::UnboundedReceiver<Incoming>>>,
+    responses: mpsc::UnboundedSender<Record>,
+}
+
+/// The other end of a `ChannelTransport`: requests sent here are handled by
+/// the consumer, and the responses come out from `responses`.
+pub struct ChannelHandle {
+    pub requests: mpsc::UnboundedSender<Incoming>,
+    pub responses: mpsc::UnboundedReceiver<Record>,
+}
+
+impl ChannelTransport {
+    pub fn new() -> (ChannelTransport, ChannelHandle) {
+        let (requests_tx, requests_rx) = mpsc::unbounded();
+        let (responses_tx, responses_rx) = mpsc::unbounded();
+
+        let transport = ChannelTransport {
+            requests: Mutex::new(Some(requests_rx)),
+            responses: responses_tx,
+        };
+
+        let handle = ChannelHandle {
+            requests: requests_tx,
+            responses: responses_rx,
+        };
+
+        (transport, handle)
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&self, record: Record) -> Delivery {
+        match self.responses.unbounded_send(record) {
+            Ok(()) => Box::new(future::ok(())),
+            Err(_) => {
+                error!("Response channel closed");
+                Box::new(future::err(()))
+            }
+        }
+    }
+
+    fn input(&self, topics: &[String], _revoked: Revoked) -> Option<Box<Input>> {
+        let requests = match self.requests.lock().unwrap().take() {
+            Some(requests) => requests,
+            None => return None,
+        };
+
+        let topic = topics.first().map(String::as_str).unwrap_or("");
+
+        Some(Box::new(LocalInput::new(Box::new(requests) as Messages, topic)))
+    }
+}
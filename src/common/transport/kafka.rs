@@ -0,0 +1,230 @@
+use rdkafka::{
+    ClientContext,
+    Message,
+    Statistics,
+    config::ClientConfig,
+    consumer::{
+        CommitMode,
+        Consumer,
+        ConsumerContext,
+        Rebalance,
+        stream_consumer::StreamConsumer,
+    },
+    error::{KafkaError, RDKafkaError},
+    message::{BorrowedMessage, Headers, OwnedHeaders},
+    producer::future_producer::{FutureProducer, FutureRecord},
+    topic_partition_list::{Offset, TopicPartitionList},
+};
+
+use futures::{Future, Stream};
+use kafka::{Config, delivered};
+use status::STATUS;
+use std::time::Duration;
+use transport::{Delivery, Incoming, Input, InputStream, Record, Revoked, Transport};
+
+/// How often librdkafka reports the broker state.
+const STATISTICS_INTERVAL_MS: &str = "5000";
+
+/// How long a seek to an earlier message can take.
+const SEEK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reports the producer connectivity to the system status.
+struct ConnectivityContext;
+
+impl ClientContext for ConnectivityContext {
+    fn stats(&self, statistics: Statistics) {
+        let connected = statistics
+            .brokers
+            .values()
+            .any(|broker| broker.state == "UP");
+
+        STATUS.set_producer_connected(connected);
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        error!("Kafka producer error"; "error" => format!("{:?}", error), "reason" => reason);
+
+        if let KafkaError::Global(RDKafkaError::AllBrokersDown) = error {
+            STATUS.set_producer_connected(false);
+        }
+    }
+}
+
+/// Lets the consumer of the input know when its partitions are revoked.
+struct InputContext {
+    revoked: Revoked,
+}
+
+impl ClientContext for InputContext {}
+
+impl ConsumerContext for InputContext {
+    fn pre_rebalance(&self, rebalance: &Rebalance) {
+        if let Rebalance::Revoke = *rebalance {
+            (self.revoked)();
+        }
+    }
+}
+
+/// Writes the records to Kafka, and reads the requests with a consumer of
+/// the group for every input.
+pub struct KafkaTransport {
+    producer: FutureProducer<ConnectivityContext>,
+    consumer_config: ClientConfig,
+    group_id: String,
+}
+
+impl KafkaTransport {
+    pub fn new(config: &Config) -> KafkaTransport {
+        let producer = config
+            .producer_config()
+            .set("produce.offset.report", "true")
+            .set("statistics.interval.ms", STATISTICS_INTERVAL_MS)
+            .create_with_context(ConnectivityContext)
+            .expect("Producer creation error");
+
+        KafkaTransport {
+            producer,
+            consumer_config: config.consumer_config(),
+            group_id: config.group_id.clone(),
+        }
+    }
+}
+
+impl Transport for KafkaTransport {
+    fn send(&self, record: Record) -> Delivery {
+        let headers = if record.headers.is_empty() {
+            None
+        } else {
+            let headers = record.headers
+                .iter()
+                .fold(OwnedHeaders::new(), |headers, &(ref name, ref value)| {
+                    headers.add(name, value)
+                });
+
+            Some(headers)
+        };
+
+        let kafka_record = FutureRecord {
+            topic: &record.topic,
+            partition: None,
+            payload: Some(&record.payload),
+            key: record.key.as_ref(),
+            timestamp: None,
+            headers,
+        };
+
+        let delivery = self.producer
+            .send::<Vec<u8>, Vec<u8>>(kafka_record, -1)
+            .then(delivered);
+
+        Box::new(delivery)
+    }
+
+    fn input(&self, topics: &[String], revoked: Revoked) -> Option<Box<Input>> {
+        let consumer = self.consumer_config
+            .clone()
+            .set("group.id", &self.group_id)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "latest")
+            .set("enable.partition.eof", "false")
+            .create_with_context(InputContext { revoked })
+            .expect("Consumer creation failed");
+
+        Some(Box::new(KafkaInput {
+            consumer,
+            topics: topics.to_vec(),
+        }))
+    }
+
+    fn flush(&self, timeout: Duration) {
+        self.producer.flush(timeout);
+    }
+}
+
+/// Reads the topics with a consumer of the group, the offsets committed
+/// explicitly.
+pub struct KafkaInput {
+    consumer: StreamConsumer<InputContext>,
+    topics: Vec<String>,
+}
+
+impl KafkaInput {
+    fn incoming(msg: &BorrowedMessage) -> Incoming {
+        let headers = msg.headers()
+            .map(|headers| {
+                (0..headers.count())
+                    .filter_map(|i| headers.get(i))
+                    .map(|(key, value)| {
+                        let value: &[u8] = value;
+                        (key.to_string(), value.to_vec())
+                    })
+                    .collect()
+            })
+            .unwrap_or_else(Vec::new);
+
+        Incoming {
+            topic: msg.topic().to_string(),
+            partition: msg.partition(),
+            offset: msg.offset(),
+            key: msg.key().map(|key| key.to_vec()),
+            headers,
+            payload: msg.payload().map(|payload| payload.to_vec()).unwrap_or_else(Vec::new),
+        }
+    }
+
+    fn partition(topic: &str, partition: i32) -> TopicPartitionList {
+        let mut list = TopicPartitionList::new();
+        list.add_partition(topic, partition);
+        list
+    }
+}
+
+impl Input for KafkaInput {
+    fn start(&self) -> Result<(), String> {
+        let topics: Vec<&str> = self.topics.iter().map(|topic| topic.as_str()).collect();
+
+        self.consumer
+            .subscribe(&topics)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn messages<'a>(&'a self) -> InputStream<'a> {
+        let messages = self.consumer
+            .start()
+            .filter_map(|result| match result {
+                Ok(msg) => Some(Self::incoming(&msg)),
+                Err(e) => {
+                    warn!("Error while receiving from Kafka: {:?}", e);
+                    None
+                }
+            });
+
+        Box::new(messages)
+    }
+
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), String> {
+        self.consumer
+            .pause(&Self::partition(topic, partition))
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), String> {
+        self.consumer
+            .resume(&Self::partition(topic, partition))
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn seek(&self, topic: &str, partition: i32, offset: i64) -> Result<(), String> {
+        self.consumer
+            .seek(topic, partition, Offset::Offset(offset), SEEK_TIMEOUT)
+            .map_err(|e| format!("{:?}", e))
+    }
+
+    fn commit(&self, offsets: &TopicPartitionList, sync: bool) -> Result<(), String> {
+        let mode = if sync { CommitMode::Sync } else { CommitMode::Async };
+
+        self.consumer
+            .commit(offsets, mode)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
@@ -0,0 +1,198 @@
+use futures::{Async, Poll, Stream, task::{self, Task}};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+use status::STATUS;
+use transport::{Incoming, Input, InputStream, Messages};
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+};
+
+/// The messages of a topic from the oldest one not committed.
+struct Log {
+    messages: VecDeque<Incoming>,
+    /// The offset of the first message.
+    start: i64,
+    /// The offset of the next message to read.
+    position: i64,
+    paused: bool,
+}
+
+impl Log {
+    fn new() -> Log {
+        Log {
+            messages: VecDeque::new(),
+            start: 0,
+            position: 0,
+            paused: false,
+        }
+    }
+
+    /// The offset of the next message appended.
+    fn end(&self) -> i64 {
+        self.start + self.messages.len() as i64
+    }
+}
+
+struct State {
+    /// The messages from the transport, `None` when closed.
+    source: Option<Messages>,
+    logs: BTreeMap<String, Log>,
+    /// The task reading the messages, to be notified when a partition
+    /// continues.
+    task: Option<Task>,
+}
+
+/// An input for the messages of a transport without topics or offsets. The
+/// messages are numbered in order in one partition of their topic, the
+/// `topic` if they don't have one, and kept until committed. So a partition
+/// can stop at a message and continue from it later, as with Kafka. The
+/// input ends when the transport is closed and all the messages are
+/// committed. The tenant configuration comes with the requests.
+pub struct LocalInput {
+    topic: String,
+    state: RefCell<State>,
+}
+
+/// The messages of a `LocalInput`.
+struct LocalMessages<'a> {
+    input: &'a LocalInput,
+}
+
+impl LocalInput {
+    pub fn new(messages: Messages, topic: &str) -> LocalInput {
+        // Nothing to replay and nowhere to connect, the configuration comes
+        // with the requests.
+        STATUS.set_config_replayed();
+        STATUS.set_producer_connected(true);
+
+        let state = State {
+            source: Some(messages),
+            logs: BTreeMap::new(),
+            task: None,
+        };
+
+        LocalInput {
+            topic: topic.to_string(),
+            state: RefCell::new(state),
+        }
+    }
+
+    /// Runs `f` for the log of the partition, notifying the reading task
+    /// to see the change.
+    fn update<F>(&self, topic: &str, partition: i32, f: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut Log),
+    {
+        let mut state = self.state.borrow_mut();
+
+        match state.logs.get_mut(topic) {
+            Some(log) if partition == 0 => f(log),
+            _ => return Err(format!("no partition {} in {}", partition, topic)),
+        }
+
+        if let Some(task) = state.task.take() {
+            task.notify();
+        }
+
+        Ok(())
+    }
+
+    fn append(&self, logs: &mut BTreeMap<String, Log>, mut incoming: Incoming) {
+        if incoming.topic.is_empty() {
+            incoming.topic = self.topic.clone();
+        }
+
+        let log = logs
+            .entry(incoming.topic.clone())
+            .or_insert_with(Log::new);
+
+        incoming.partition = 0;
+        incoming.offset = log.end();
+
+        log.messages.push_back(incoming);
+    }
+}
+
+impl Input for LocalInput {
+    fn start(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn messages<'a>(&'a self) -> InputStream<'a> {
+        Box::new(LocalMessages { input: self })
+    }
+
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), String> {
+        self.update(topic, partition, |log| log.paused = true)
+    }
+
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), String> {
+        self.update(topic, partition, |log| log.paused = false)
+    }
+
+    fn seek(&self, topic: &str, partition: i32, offset: i64) -> Result<(), String> {
+        self.update(topic, partition, |log| {
+            log.position = offset.max(log.start).min(log.end());
+        })
+    }
+
+    /// Forgets the messages before the committed offsets.
+    fn commit(&self, offsets: &TopicPartitionList, _sync: bool) -> Result<(), String> {
+        for element in offsets.elements() {
+            if let Offset::Offset(offset) = element.offset() {
+                self.update(element.topic(), element.partition(), |log| {
+                    while log.start < offset && !log.messages.is_empty() {
+                        log.messages.pop_front();
+                        log.start += 1;
+                    }
+
+                    log.position = log.position.max(log.start);
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn has_configs(&self) -> bool {
+        true
+    }
+}
+
+impl<'a> Stream for LocalMessages<'a> {
+    type Item = Incoming;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<Incoming>, ()> {
+        let mut state = self.input.state.borrow_mut();
+        let state = &mut *state;
+
+        while let Some(result) = state.source.as_mut().map(|source| source.poll()) {
+            match result {
+                Ok(Async::Ready(Some(incoming))) => self.input.append(&mut state.logs, incoming),
+                Ok(Async::NotReady) => break,
+                Ok(Async::Ready(None)) | Err(()) => state.source = None,
+            }
+        }
+
+        for log in state.logs.values_mut() {
+            if !log.paused && log.position < log.end() {
+                let index = (log.position - log.start) as usize;
+                log.position += 1;
+
+                return Ok(Async::Ready(Some(log.messages[index].clone())));
+            }
+        }
+
+        let committed = state.logs.values().all(|log| log.messages.is_empty());
+
+        if state.source.is_none() && committed {
+            return Ok(Async::Ready(None));
+        }
+
+        state.task = Some(task::current());
+
+        Ok(Async::NotReady)
+    }
+}
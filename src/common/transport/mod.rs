@@ -0,0 +1,129 @@
+mod channel;
+mod kafka;
+mod local;
+mod stdio;
+
+pub use self::channel::{ChannelHandle, ChannelTransport};
+pub use self::kafka::KafkaTransport;
+pub use self::local::LocalInput;
+pub use self::stdio::StdioTransport;
+
+use config::Config;
+use futures::{Future, Stream};
+use rdkafka::topic_partition_list::TopicPartitionList;
+use std::{str, sync::Arc, time::Duration};
+
+/// Resolves when the record is delivered, fails if it could not be written.
+pub type Delivery = Box<Future<Item = (), Error = ()> + 'static + Send>;
+
+/// Messages read from a local transport, ending when the input is closed.
+pub type Messages = Box<Stream<Item = Incoming, Error = ()> + 'static + Send>;
+
+/// Messages read from an input, ending when the input is closed.
+pub type InputStream<'a> = Box<Stream<Item = Incoming, Error = ()> + 'a>;
+
+/// Called when the partitions of an input are taken away, before the input
+/// continues from the committed offsets.
+pub type Revoked = Box<Fn() + Send + Sync>;
+
+/// A message to be written to the transport.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+/// A message read from the transport, with its place in the input. The
+/// messages of the local transports have no topic and are numbered by the
+/// `LocalInput`.
+#[derive(Debug, Clone, Default)]
+pub struct Incoming {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub payload: Vec<u8>,
+}
+
+impl Incoming {
+    /// The first header with the given name, if valid UTF-8.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .and_then(|&(_, ref value)| str::from_utf8(value).ok())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransportType {
+    /// Requests and configuration from Kafka, responses to Kafka.
+    Kafka,
+    /// Length-delimited protobuf messages from stdin, responses to stdout
+    /// and other records, such as retries, logged to stderr. For local
+    /// debugging.
+    Stdio,
+}
+
+impl Default for TransportType {
+    fn default() -> TransportType {
+        TransportType::Kafka
+    }
+}
+
+/// How the requests come in and the responses go out.
+pub trait Transport: Send + Sync {
+    /// Writes the record.
+    fn send(&self, record: Record) -> Delivery;
+
+    /// An input for the topics of a lane, calling `revoked` when its
+    /// partitions are taken away. Kafka reads the topics with a consumer of
+    /// the group. The transports without topics give all their messages to
+    /// the first input, the others get `None`.
+    fn input(&self, topics: &[String], revoked: Revoked) -> Option<Box<Input>>;
+
+    /// Waits for the queued records to be written, at most `timeout`.
+    fn flush(&self, _timeout: Duration) {}
+}
+
+/// Where the requests come from. A partition can be stopped at a message and
+/// continued from it later, and the offsets of the handled messages are
+/// committed for a restart.
+pub trait Input {
+    /// Starts reading the topics, called when the tenants are configured.
+    fn start(&self) -> Result<(), String>;
+
+    /// The messages of the topics, ending when the input is closed.
+    fn messages<'a>(&'a self) -> InputStream<'a>;
+
+    /// Stops fetching the partition.
+    fn pause(&self, topic: &str, partition: i32) -> Result<(), String>;
+
+    /// Continues fetching the partition.
+    fn resume(&self, topic: &str, partition: i32) -> Result<(), String>;
+
+    /// Continues the partition from the offset.
+    fn seek(&self, topic: &str, partition: i32, offset: i64) -> Result<(), String>;
+
+    /// Stores the offsets the partitions continue from, waiting for the
+    /// commit to finish if `sync`.
+    fn commit(&self, offsets: &TopicPartitionList, sync: bool) -> Result<(), String>;
+
+    /// True if the tenant configuration comes with the requests, instead of
+    /// the config topic.
+    fn has_configs(&self) -> bool {
+        false
+    }
+}
+
+/// The transport selected in the configuration.
+pub fn build(config: &Config) -> Arc<Transport> {
+    match config.transport {
+        TransportType::Kafka => Arc::new(KafkaTransport::new(&config.kafka)),
+        TransportType::Stdio => Arc::new(StdioTransport::new(&config.kafka)),
+    }
+}
@@ -0,0 +1,133 @@
+use base64;
+use futures::{Future, Sink, future, sync::mpsc};
+use kafka::Config;
+use protobuf::{CodedInputStream, CodedOutputStream, ProtobufResult};
+use transport::{Delivery, Incoming, Input, LocalInput, Messages, Record, Revoked, Transport};
+
+use std::{
+    io::{self, Write},
+    sync::{Mutex, atomic::{AtomicBool, Ordering}},
+    thread,
+};
+
+/// Reads length-delimited protobuf messages from stdin and writes the
+/// responses to stdout in the same format. Every message is a varint with
+/// the length, followed by the serialized event. Records to other topics,
+/// such as retries and dead letters, are logged to stderr with their topic
+/// instead, so stdout has only responses.
+pub struct StdioTransport {
+    stdout: Mutex<io::Stdout>,
+    reading: AtomicBool,
+    response_topics: Vec<String>,
+}
+
+impl StdioTransport {
+    /// The responses are the records to the output topic or the reply
+    /// topics of the configuration.
+    pub fn new(config: &Config) -> StdioTransport {
+        let mut response_topics = config.reply_topics.clone();
+        response_topics.push(config.output_topic.clone());
+
+        StdioTransport {
+            stdout: Mutex::new(io::stdout()),
+            reading: AtomicBool::new(false),
+            response_topics,
+        }
+    }
+
+    fn write(&self, payload: &[u8]) -> ProtobufResult<()> {
+        let mut stdout = self.stdout.lock().unwrap();
+
+        {
+            let mut output = CodedOutputStream::new(&mut *stdout);
+            output.write_raw_varint32(payload.len() as u32)?;
+            output.write_raw_bytes(payload)?;
+            output.flush()?;
+        }
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+}
+
+impl Transport for StdioTransport {
+    fn send(&self, record: Record) -> Delivery {
+        if !self.response_topics.contains(&record.topic) {
+            let headers: Vec<String> = record.headers
+                .iter()
+                .map(|&(ref name, ref value)| {
+                    format!("{}={}", name, String::from_utf8_lossy(value))
+                })
+                .collect();
+
+            info!(
+                "Not a response, skipping stdout";
+                "topic" => &record.topic,
+                "key" => record.key.as_ref().map(|key| String::from_utf8_lossy(key).into_owned()),
+                "headers" => headers.join(", "),
+                "payload" => base64::encode(&record.payload)
+            );
+
+            return Box::new(future::ok(()));
+        }
+
+        match self.write(&record.payload) {
+            Ok(()) => Box::new(future::ok(())),
+            Err(e) => {
+                error!("Couldn't write the response to stdout"; "error" => format!("{:?}", e));
+                Box::new(future::err(()))
+            }
+        }
+    }
+
+    fn input(&self, topics: &[String], _revoked: Revoked) -> Option<Box<Input>> {
+        if self.reading.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+
+        let (tx, rx) = mpsc::channel(1);
+
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            let mut input = CodedInputStream::new(&mut stdin);
+            let mut tx = tx;
+
+            loop {
+                match input.eof() {
+                    Ok(true) => break,
+                    Ok(false) => (),
+                    Err(e) => {
+                        error!("Couldn't read from stdin"; "error" => format!("{:?}", e));
+                        break;
+                    }
+                }
+
+                let payload = match input.read_bytes() {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        error!("Invalid message in stdin"; "error" => format!("{:?}", e));
+                        break;
+                    }
+                };
+
+                let incoming = Incoming {
+                    payload,
+                    ..Default::default()
+                };
+
+                tx = match tx.send(incoming).wait() {
+                    Ok(tx) => tx,
+                    Err(_) => break,
+                };
+            }
+
+            info!("Stdin closed");
+        });
+
+        let topic = topics.first().map(String::as_str).unwrap_or("");
+
+        Some(Box::new(LocalInput::new(Box::new(rx) as Messages, topic)))
+    }
+}
@@ -6,13 +6,15 @@ use common::{
         push_notification::PushNotification,
         http_request::HttpRequest,
     },
-    kafka::EventHandler,
-    metrics::*
+    kafka::{EventHandler, Routing},
+    metrics::*,
+    tenants::{redact, Connection, TENANTS},
+    transport::Transport,
 };
 
 use futures::{Future, future::ok};
 
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use notifier::Notifier;
 use producer::FcmProducer;
 
@@ -23,9 +25,9 @@ pub struct FcmHandler {
 }
 
 impl FcmHandler {
-    pub fn new() -> FcmHandler {
+    pub fn new(transport: Arc<Transport>) -> FcmHandler {
         let api_keys = RwLock::new(HashMap::new());
-        let producer = FcmProducer::new();
+        let producer = FcmProducer::new(transport);
         let notifier = Notifier::new();
 
         FcmHandler {
@@ -38,6 +40,7 @@ impl FcmHandler {
     fn delete_key(&self, id: &str) {
         if self.api_keys.write().unwrap().remove(id).is_some() {
             self.set_app_counter();
+            TENANTS.removed(id);
             info!("Application removed"; "universe" => id);
         };
     }
@@ -54,7 +57,7 @@ impl EventHandler for FcmHandler {
 
     fn handle_notification(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
         let timer = RESPONSE_TIMES_HISTOGRAM.start_timer();
@@ -71,20 +74,19 @@ impl EventHandler for FcmHandler {
                         CALLBACKS_INFLIGHT.dec();
 
                         match result {
-                            Ok(response) => producer.handle_response(key, event, response),
-                            Err(error) => producer.handle_error(key, event, error),
+                            Ok(response) => producer.handle_response(routing, event, response),
+                            Err(error) => producer.handle_error(routing, event, error),
                         }
-                    })
-                    .then(|_| ok(())),
+                    }),
             )
         } else {
-            Box::new(self.producer.handle_no_cert(key, event).then(|_| ok(())))
+            self.producer.handle_no_cert(routing, event)
         }
     }
 
     fn handle_http(
         &self,
-        _: Option<Vec<u8>>,
+        _: Routing,
         _: HttpRequest
     ) -> Box<Future<Item=(), Error=()> + 'static + Send> {
         warn!("We don't handle http request events here");
@@ -129,8 +131,83 @@ impl EventHandler for FcmHandler {
                     String::from(api_key),
                 );
 
+                TENANTS.loaded(application_id, Connection {
+                    connection_type: String::from("api_key"),
+                    credentials: Some(redact(api_key)),
+                    ..Default::default()
+                });
+
                 self.set_app_counter();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::{
+        events::push_result::{PushResult, PushResult_ResponseAction as ResponseAction},
+        kafka::RequestConsumer,
+        transport::{ChannelHandle, ChannelTransport, Incoming},
+    };
+    use futures::{Stream, sync::oneshot};
+    use protobuf::{Message, parse_from_bytes};
+    use CONFIG;
+
+    fn notification(universe: &str, google: bool) -> PushNotification {
+        let mut event = PushNotification::new();
+        event.set_universe(universe.to_string());
+        event.mut_header().set_field_type("notification.PushNotification".to_string());
+
+        if google {
+            event.mut_google();
+        }
+
+        event
+    }
+
+    /// Handles the notifications through a channel, returning the responses.
+    fn handle(events: Vec<PushNotification>) -> Vec<PushResult> {
+        let (transport, ChannelHandle { requests, mut responses }) = ChannelTransport::new();
+        let transport: Arc<Transport> = Arc::new(transport);
+        let handler = FcmHandler::new(transport.clone());
+
+        for event in events {
+            let incoming = Incoming {
+                payload: event.write_to_bytes().unwrap(),
+                ..Default::default()
+            };
+
+            requests.unbounded_send(incoming).unwrap();
+        }
+
+        drop(requests);
+
+        let (_control_tx, control_rx) = oneshot::channel();
+
+        RequestConsumer::new(handler, transport, &CONFIG.kafka)
+            .handle_requests(control_rx)
+            .unwrap();
+
+        responses.close();
+
+        responses
+            .wait()
+            .map(|record| parse_from_bytes(&record.unwrap().payload).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn retries_notifications_of_tenants_without_an_api_key() {
+        let results = handle(vec![notification("unconfigured", true)]);
+
+        assert_eq!(1, results.len());
+        assert_eq!(ResponseAction::Retry, results[0].get_response_action());
+    }
+
+    #[test]
+    fn skips_notifications_without_google_data() {
+        assert!(handle(vec![notification("unconfigured", false)]).is_empty());
+    }
+}
@@ -5,27 +5,33 @@
 extern crate common;
 extern crate fcm;
 extern crate futures;
+#[cfg(test)] extern crate protobuf;
 
 mod consumer;
 mod notifier;
 mod producer;
 
-use common::{config::Config, system::System};
+use common::{config::{self, Config, Options}, system::System, transport};
 
 use consumer::FcmHandler;
-use std::env;
+
+const DEFAULT_CONFIG: &str = "./config/fcm.toml";
 
 lazy_static! {
-    pub static ref CONFIG: Config = match env::var("CONFIG") {
-        Ok(config_file_location) => Config::parse(&config_file_location),
-        _ => Config::parse("./config/fcm.toml"),
-    };
+    pub static ref CONFIG: Config = Config::parse(&config::location(DEFAULT_CONFIG));
 }
 
 fn main() {
+    if Options::parse("Sends push notifications to Firebase Cloud Messaging").check_config {
+        Config::check(&config::location(DEFAULT_CONFIG));
+    }
+
+    let transport = transport::build(&CONFIG);
+
     System::start(
         "fcm",
-        FcmHandler::new(),
+        FcmHandler::new(transport.clone()),
+        transport,
         &CONFIG,
     );
 }
@@ -1,16 +1,16 @@
 use common::{
     events::{
-        push_result::{
-            PushResult,
-            PushResult_ResponseAction as ResponseAction
-        },
+        push_result::PushResult_ResponseAction as ResponseAction,
         push_notification::PushNotification,
     },
-    kafka::{DeliveryFuture, ResponseProducer},
-    metrics::CALLBACKS_COUNTER
+    kafka::{ResponseProducer, Routing},
+    metrics::CALLBACKS_COUNTER,
+    tenants::TENANTS,
+    transport::{Delivery, Transport},
 };
 
 use fcm::response::{FcmError, FcmResponse, ErrorReason::*};
+use std::sync::Arc;
 use CONFIG;
 
 pub struct FcmProducer {
@@ -18,17 +18,17 @@ pub struct FcmProducer {
 }
 
 impl FcmProducer {
-    pub fn new() -> FcmProducer {
+    pub fn new(transport: Arc<Transport>) -> FcmProducer {
         FcmProducer {
-            producer: ResponseProducer::new(&CONFIG.kafka),
+            producer: ResponseProducer::new(&CONFIG.kafka, transport),
         }
     }
 
     pub fn handle_no_cert(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
         error!(
             "No FCM key set for application";
@@ -38,17 +38,18 @@ impl FcmProducer {
 
         CALLBACKS_COUNTER.with_label_values(&["certificate_missing"]).inc();
 
-        let result: PushResult = (event, ResponseAction::Retry).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, ResponseAction::Retry)
     }
 
     pub fn handle_error(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
         error: FcmError
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
+        TENANTS.error(event.get_universe(), &format!("{:?}", error));
+
         error!(
             "Error sending a push notification";
             &event,
@@ -72,16 +73,15 @@ impl FcmProducer {
                 }
             };
 
-        let result: PushResult = (event, response_action).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, response_action)
     }
 
     pub fn handle_response(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
         response: FcmResponse,
-    ) -> DeliveryFuture {
+    ) -> Delivery {
         let error = response
             .results
             .as_ref()
@@ -92,6 +92,7 @@ impl FcmProducer {
             if let Some(ref error) = error {
                 let status_str = format!("{:?}", error);
                 CALLBACKS_COUNTER.with_label_values(&[&status_str]).inc();
+                TENANTS.error(event.get_universe(), &status_str);
 
                 error!(
                     "Error sending a push notification";
@@ -105,11 +106,11 @@ impl FcmProducer {
                     _             => ResponseAction::None,
                 }
             } else {
+                TENANTS.success(event.get_universe());
                 ResponseAction::None
             };
 
-        let result: PushResult = (event, response_action).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, response_action)
     }
 }
 
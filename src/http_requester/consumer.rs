@@ -4,13 +4,15 @@ use common::{
         push_notification::PushNotification,
         http_request::HttpRequest,
     },
-    kafka::EventHandler,
-    metrics::*
+    kafka::{EventHandler, Routing},
+    metrics::*,
+    transport::Transport,
 };
 
 use futures::{Future, future::ok};
 use requester::Requester;
 use producer::HttpResponseProducer;
+use std::sync::Arc;
 
 pub struct HttpRequestHandler {
     producer: HttpResponseProducer,
@@ -18,8 +20,8 @@ pub struct HttpRequestHandler {
 }
 
 impl HttpRequestHandler {
-    pub fn new() -> HttpRequestHandler {
-        let producer = HttpResponseProducer::new();
+    pub fn new(transport: Arc<Transport>) -> HttpRequestHandler {
+        let producer = HttpResponseProducer::new(transport);
         let requester = Requester::new();
 
         HttpRequestHandler {
@@ -34,7 +36,7 @@ impl EventHandler for HttpRequestHandler {
 
     fn handle_notification(
         &self,
-        _: Option<Vec<u8>>,
+        _: Routing,
         _: PushNotification,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
         warn!("We don't handle push notification events here");
@@ -43,7 +45,7 @@ impl EventHandler for HttpRequestHandler {
 
     fn handle_http(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: HttpRequest,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
         let producer = self.producer.clone();
@@ -55,9 +57,8 @@ impl EventHandler for HttpRequestHandler {
             .then(move |response| {
                 timer.observe_duration();
                 CALLBACKS_INFLIGHT.dec();
-                producer.respond(key, event, response)
-            })
-            .then(|_| ok(()));
+                producer.respond(routing, event, response)
+            });
 
         Box::new(request_send)
     }
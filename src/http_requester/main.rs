@@ -17,18 +17,27 @@ mod consumer;
 mod requester;
 mod producer;
 
-use common::{config::Config, system::System};
+use common::{config::{self, Config, Options}, system::System, transport};
 
 use consumer::HttpRequestHandler;
-use std::env;
+
+const DEFAULT_CONFIG: &str = "./config/http_requester.toml";
 
 lazy_static! {
-    pub static ref CONFIG: Config = match env::var("CONFIG") {
-        Ok(config_file_location) => Config::parse(&config_file_location),
-        _ => Config::parse("./config/http_requester.toml"),
-    };
+    pub static ref CONFIG: Config = Config::parse(&config::location(DEFAULT_CONFIG));
 }
 
 fn main() {
-    System::start("http_requester", HttpRequestHandler::new(), &CONFIG);
+    if Options::parse("Sends HTTP requests from Kafka events").check_config {
+        Config::check(&config::location(DEFAULT_CONFIG));
+    }
+
+    let transport = transport::build(&CONFIG);
+
+    System::start(
+        "http_requester",
+        HttpRequestHandler::new(transport.clone()),
+        transport,
+        &CONFIG,
+    );
 }
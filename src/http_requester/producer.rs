@@ -5,12 +5,13 @@ use common::{
         rpc::{Response},
     },
     kafka::{
-        DeliveryFuture,
-        ResponseProducer
+        ResponseProducer,
+        Routing,
     },
-    metrics::*
+    metrics::*,
+    transport::{Delivery, Transport},
 };
-use std::{collections::HashMap, str};
+use std::{collections::HashMap, str, sync::Arc};
 use requester::{HttpResult, RequestError};
 
 use CONFIG;
@@ -20,18 +21,18 @@ pub struct HttpResponseProducer {
 }
 
 impl HttpResponseProducer {
-    pub fn new() -> HttpResponseProducer {
+    pub fn new(transport: Arc<Transport>) -> HttpResponseProducer {
         HttpResponseProducer {
-            producer: ResponseProducer::new(&CONFIG.kafka)
+            producer: ResponseProducer::new(&CONFIG.kafka, transport)
         }
     }
 
     pub fn respond(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         mut event: HttpRequest,
         result: Result<HttpResult, RequestError>
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
         let mut header = Response::new();
         header.set_field_type("http.HttpResponse".to_string());
@@ -97,7 +98,7 @@ impl HttpResponseProducer {
             }
         }
 
-        self.producer.publish(key, &response)
+        self.producer.publish(routing, &response)
     }
 }
 
@@ -6,12 +6,14 @@ use common::{
         push_notification::PushNotification,
         http_request::HttpRequest,
     },
-    kafka::EventHandler,
-    metrics::*
+    kafka::{EventHandler, Routing},
+    metrics::*,
+    tenants::{redact, Connection, TENANTS},
+    transport::Transport,
 };
 
 use futures::{Future, future::ok};
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 use notifier::Notifier;
 use producer::WebPushProducer;
 
@@ -26,9 +28,9 @@ pub struct WebPushHandler {
 }
 
 impl WebPushHandler {
-    pub fn new() -> WebPushHandler {
+    pub fn new(transport: Arc<Transport>) -> WebPushHandler {
         let fcm_api_keys = RwLock::new(HashMap::new());
-        let producer = WebPushProducer::new();
+        let producer = WebPushProducer::new(transport);
         let notifier = Notifier::new();
 
         WebPushHandler {
@@ -41,6 +43,7 @@ impl WebPushHandler {
     fn delete_key(&self, id: &str) {
         if self.fcm_api_keys.write().unwrap().remove(id).is_some() {
             self.set_app_counter();
+            TENANTS.removed(id);
             info!("Application removed"; "universe" => id);
         };
     }
@@ -57,7 +60,7 @@ impl EventHandler for WebPushHandler {
 
     fn handle_notification(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
     ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
         let producer = self.producer.clone();
@@ -74,21 +77,20 @@ impl EventHandler for WebPushHandler {
                         CALLBACKS_INFLIGHT.dec();
 
                         match result {
-                            Ok(()) => producer.handle_ok(key, event),
-                            Err(error) => producer.handle_error(key, event, &error),
+                            Ok(()) => producer.handle_ok(routing, event),
+                            Err(error) => producer.handle_error(routing, event, &error),
                         }
-                    })
-                    .then(|_| ok(()));
+                    });
 
                 Box::new(notification_send)
             }
-            None => Box::new(self.producer.handle_no_cert(key, event).then(|_| ok(()))),
+            None => self.producer.handle_no_cert(routing, event),
         }
     }
 
     fn handle_http(
         &self,
-        _: Option<Vec<u8>>,
+        _: Routing,
         _: HttpRequest
     ) -> Box<Future<Item=(), Error=()> + 'static + Send> {
         warn!("We don't handle http request events here");
@@ -126,6 +128,12 @@ impl EventHandler for WebPushHandler {
                         "fcm_api_key" => &api_key
                     );
 
+                    TENANTS.loaded(application_id, Connection {
+                        connection_type: String::from("fcm_api_key"),
+                        credentials: Some(redact(&api_key)),
+                        ..Default::default()
+                    });
+
                     self.fcm_api_keys.write().unwrap().insert(
                         String::from(application_id),
                         ApiKey { fcm_api_key: Some(api_key), },
@@ -136,6 +144,11 @@ impl EventHandler for WebPushHandler {
                         &application,
                     );
 
+                    TENANTS.loaded(application_id, Connection {
+                        connection_type: String::from("web_push"),
+                        ..Default::default()
+                    });
+
                     self.fcm_api_keys
                         .write()
                         .unwrap()
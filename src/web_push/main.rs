@@ -12,22 +12,27 @@ mod consumer;
 mod notifier;
 mod producer;
 
-use common::{config::Config, system::System};
+use common::{config::{self, Config, Options}, system::System, transport};
 
 use consumer::WebPushHandler;
-use std::env;
+
+const DEFAULT_CONFIG: &str = "./config/web_push.toml";
 
 lazy_static! {
-    pub static ref CONFIG: Config = match env::var("CONFIG") {
-        Ok(config_file_location) => Config::parse(&config_file_location),
-        _ => Config::parse("./config/web_push.toml"),
-    };
+    pub static ref CONFIG: Config = Config::parse(&config::location(DEFAULT_CONFIG));
 }
 
 fn main() {
+    if Options::parse("Sends web push notifications").check_config {
+        Config::check(&config::location(DEFAULT_CONFIG));
+    }
+
+    let transport = transport::build(&CONFIG);
+
     System::start(
         "web_push",
-        WebPushHandler::new(),
+        WebPushHandler::new(transport.clone()),
+        transport,
         &CONFIG,
     );
 }
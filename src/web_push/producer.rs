@@ -1,15 +1,15 @@
 use common::{
     events::{
         push_notification::PushNotification,
-        push_result::{
-            PushResult,
-            PushResult_ResponseAction as ResponseAction
-        },
+        push_result::PushResult_ResponseAction as ResponseAction,
     },
-    kafka::{DeliveryFuture, ResponseProducer},
-    metrics::CALLBACKS_COUNTER
+    kafka::{ResponseProducer, Routing},
+    metrics::CALLBACKS_COUNTER,
+    tenants::TENANTS,
+    transport::{Delivery, Transport},
 };
 
+use std::sync::Arc;
 use CONFIG;
 
 use web_push::{*, WebPushError::*};
@@ -19,17 +19,17 @@ pub struct WebPushProducer {
 }
 
 impl WebPushProducer {
-    pub fn new() -> WebPushProducer {
+    pub fn new(transport: Arc<Transport>) -> WebPushProducer {
         WebPushProducer {
-            producer: ResponseProducer::new(&CONFIG.kafka),
+            producer: ResponseProducer::new(&CONFIG.kafka, transport),
         }
     }
 
     pub fn handle_ok(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
         info!(
             "Successfully sent a push notification";
@@ -38,16 +38,16 @@ impl WebPushProducer {
         );
 
         CALLBACKS_COUNTER.with_label_values(&["success"]).inc();
+        TENANTS.success(event.get_universe());
 
-        let result: PushResult = (event, ResponseAction::None).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, ResponseAction::None)
     }
 
     pub fn handle_no_cert(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
         error!(
             "Application is not configured to send web push messages";
@@ -57,17 +57,18 @@ impl WebPushProducer {
 
         CALLBACKS_COUNTER.with_label_values(&["certificate_missing"]).inc();
 
-        let result: PushResult = (event, ResponseAction::Retry).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, ResponseAction::Retry)
     }
 
     pub fn handle_error(
         &self,
-        key: Option<Vec<u8>>,
+        routing: Routing,
         event: PushNotification,
         error: &WebPushError
-    ) -> DeliveryFuture
+    ) -> Delivery
     {
+        TENANTS.error(event.get_universe(), &format!("{:?}", error));
+
         error!(
             "Error sending a push notification";
             &event,
@@ -84,8 +85,7 @@ impl WebPushProducer {
 
         CALLBACKS_COUNTER.with_label_values(&[error.short_description()]).inc();
 
-        let result: PushResult = (event, response_action).into();
-        self.producer.publish(key, &result)
+        self.producer.respond(routing, event, response_action)
     }
 }
 
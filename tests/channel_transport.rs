@@ -0,0 +1,279 @@
+extern crate common;
+extern crate futures;
+extern crate protobuf;
+extern crate toml;
+
+use common::{
+    events::{
+        application::Application,
+        http_request::HttpRequest,
+        push_notification::PushNotification,
+        push_result::{PushResult, PushResult_ResponseAction as ResponseAction},
+        rpc_decoder::RequestWrapper,
+    },
+    kafka::{self, EventHandler, RequestConsumer, ResponseProducer, Routing},
+    transport::{ChannelHandle, ChannelTransport, Incoming, Record, Transport},
+};
+use futures::{Future, Stream, future::ok, sync::oneshot};
+use protobuf::{Message, parse_from_bytes};
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CONFIG: &str = r#"
+input_topic  = "rpc.push-notification"
+config_topic = "applications"
+output_topic = "rpc.responses"
+group_id     = "test"
+brokers      = "localhost:9092"
+
+dead_letter_topic = "rpc.dead-letters"
+reply_topics      = ["rpc.responses.web"]
+
+[[retry_topics]]
+topic    = "rpc.push-notification.retry"
+delay_ms = 1000
+
+[[schedule_topics]]
+topic    = "rpc.push-notification.scheduled.1s"
+delay_ms = 1000
+
+[[schedule_topics]]
+topic    = "rpc.push-notification.scheduled.1m"
+delay_ms = 60000
+"#;
+
+/// Responds to the notifications of the configured tenants with the action.
+struct TestHandler {
+    producer: ResponseProducer,
+    tenants: Mutex<HashSet<String>>,
+    action: ResponseAction,
+}
+
+impl EventHandler for TestHandler {
+    fn accepts(&self, event: &PushNotification) -> bool {
+        self.tenants.lock().unwrap().contains(event.get_universe())
+    }
+
+    fn handle_notification(
+        &self,
+        routing: Routing,
+        event: PushNotification,
+    ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
+        self.producer.respond(routing, event, self.action)
+    }
+
+    fn handle_http(
+        &self,
+        _: Routing,
+        _: HttpRequest,
+    ) -> Box<Future<Item = (), Error = ()> + 'static + Send> {
+        Box::new(ok(()))
+    }
+
+    fn handle_config(&self, id: &str, config: Option<Application>) {
+        let mut tenants = self.tenants.lock().unwrap();
+
+        match config {
+            Some(_) => tenants.insert(id.to_string()),
+            None => tenants.remove(id),
+        };
+    }
+}
+
+fn application(id: &str) -> Incoming {
+    let mut application = Application::new();
+    application.set_id(id.to_string());
+    application.mut_header().set_field_type("application.Application".to_string());
+
+    Incoming {
+        payload: application.write_to_bytes().unwrap(),
+        ..Default::default()
+    }
+}
+
+fn removal(id: &str) -> Incoming {
+    Incoming {
+        key: Some(id.as_bytes().to_vec()),
+        ..Default::default()
+    }
+}
+
+fn notification(universe: &str, correlation_id: &str) -> Incoming {
+    let mut event = PushNotification::new();
+    event.set_universe(universe.to_string());
+    event.mut_header().set_field_type("notification.PushNotification".to_string());
+    event.mut_header().set_correlation_id(correlation_id.to_string());
+
+    Incoming {
+        key: Some(universe.as_bytes().to_vec()),
+        headers: vec![("trace_id".to_string(), b"abc".to_vec())],
+        payload: event.write_to_bytes().unwrap(),
+        ..Default::default()
+    }
+}
+
+fn invalid(field_type: &str) -> Incoming {
+    let mut wrapper = RequestWrapper::new();
+    wrapper.mut_header().set_field_type(field_type.to_string());
+
+    Incoming {
+        payload: wrapper.write_to_bytes().unwrap(),
+        ..Default::default()
+    }
+}
+
+fn header<'a>(record: &'a Record, name: &str) -> Option<&'a [u8]> {
+    record.headers
+        .iter()
+        .find(|&&(ref key, _)| key == name)
+        .map(|&(_, ref value)| value.as_slice())
+}
+
+/// Handles the messages with the handler responding `action`, returning
+/// the records written to the transport.
+fn handle(messages: Vec<Incoming>, action: ResponseAction) -> Vec<Record> {
+    let config: kafka::Config = toml::from_str(CONFIG).unwrap();
+    let (transport, ChannelHandle { requests, mut responses }) = ChannelTransport::new();
+    let transport: Arc<Transport> = Arc::new(transport);
+
+    let handler = TestHandler {
+        producer: ResponseProducer::new(&config, transport.clone()),
+        tenants: Mutex::new(HashSet::new()),
+        action,
+    };
+
+    for message in messages {
+        requests.unbounded_send(message).unwrap();
+    }
+
+    drop(requests);
+
+    let (_control_tx, control_rx) = oneshot::channel();
+
+    RequestConsumer::new(handler, transport, &config)
+        .handle_requests(control_rx)
+        .unwrap();
+
+    responses.close();
+    responses.wait().map(|record| record.unwrap()).collect()
+}
+
+#[test]
+fn responds_to_notifications_of_configured_tenants() {
+    let records = handle(
+        vec![application("tenant"), notification("tenant", "1")],
+        ResponseAction::None,
+    );
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.responses", records[0].topic);
+    assert_eq!(Some(b"tenant".to_vec()), records[0].key);
+
+    let result: PushResult = parse_from_bytes(&records[0].payload).unwrap();
+
+    assert_eq!(ResponseAction::None, result.get_response_action());
+    assert_eq!("1", result.get_header().get_request().get_correlation_id());
+}
+
+#[test]
+fn skips_notifications_of_unknown_tenants() {
+    let records = handle(
+        vec![notification("unknown", "1")],
+        ResponseAction::None,
+    );
+
+    assert!(records.is_empty());
+}
+
+#[test]
+fn skips_notifications_of_removed_tenants() {
+    let records = handle(
+        vec![application("tenant"), removal("tenant"), notification("tenant", "1")],
+        ResponseAction::None,
+    );
+
+    assert!(records.is_empty());
+}
+
+#[test]
+fn sends_retries_to_the_retry_topic_with_the_request_headers() {
+    let records = handle(
+        vec![application("tenant"), notification("tenant", "1")],
+        ResponseAction::Retry,
+    );
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.push-notification.retry", records[0].topic);
+
+    let header_names: Vec<&str> = records[0].headers
+        .iter()
+        .map(|&(ref name, _)| name.as_str())
+        .collect();
+
+    assert_eq!(vec!["trace_id", "retry_attempt", "retry_at"], header_names);
+    assert_eq!(b"1".to_vec(), records[0].headers[1].1);
+
+    let event: PushNotification = parse_from_bytes(&records[0].payload).unwrap();
+
+    assert_eq!("1", event.get_header().get_correlation_id());
+}
+
+#[test]
+fn responds_to_the_requested_reply_topic() {
+    let mut request = notification("tenant", "1");
+    request.headers.push(("reply_to".to_string(), b"rpc.responses.web".to_vec()));
+
+    let records = handle(vec![application("tenant"), request], ResponseAction::None);
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.responses.web", records[0].topic);
+}
+
+#[test]
+fn responds_to_the_output_topic_if_the_reply_topic_is_not_allowed() {
+    let mut request = notification("tenant", "1");
+    request.headers.push(("reply_to".to_string(), b"rpc.other".to_vec()));
+
+    let records = handle(vec![application("tenant"), request], ResponseAction::None);
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.responses", records[0].topic);
+}
+
+#[test]
+fn sends_invalid_requests_to_the_dead_letter_topic() {
+    let records = handle(vec![invalid("unknown.Request")], ResponseAction::None);
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.dead-letters", records[0].topic);
+
+    assert_eq!(
+        Some(&b"invalid_type: unknown.Request"[..]),
+        header(&records[0], "dead_letter_reason")
+    );
+
+    assert_eq!(
+        Some(&b"rpc.push-notification"[..]),
+        header(&records[0], "source_topic")
+    );
+}
+
+#[test]
+fn moves_scheduled_requests_to_the_schedule_topic_with_the_longest_delay_until_due() {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let send_at = elapsed.as_secs() * 1000 + 120_000;
+
+    let mut request = notification("tenant", "1");
+    request.headers.push(("send_at".to_string(), send_at.to_string().into_bytes()));
+
+    let records = handle(vec![application("tenant"), request], ResponseAction::None);
+
+    assert_eq!(1, records.len());
+    assert_eq!("rpc.push-notification.scheduled.1m", records[0].topic);
+    assert_eq!(Some(send_at.to_string().as_bytes()), header(&records[0], "send_at"));
+    assert!(header(&records[0], "schedule_at").is_some());
+}